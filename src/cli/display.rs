@@ -7,9 +7,10 @@ use console::Style;
 use similar::{ChangeTag, TextDiff};
 use std::path::Path;
 
-pub fn display_checkpoints_table(
-    checkpoints: Vec<(String, String, Option<String>, i64, Option<String>)>,
-) {
+/// A `checkpoints` row as displayed: (id, track, parent, timestamp, note)
+pub type CheckpointRow = (String, String, Option<String>, i64, Option<String>);
+
+pub fn display_checkpoints_table(checkpoints: Vec<CheckpointRow>) {
     let mut table = Table::new();
     table
         .load_preset(UTF8_FULL)
@@ -41,10 +42,7 @@ pub fn display_checkpoints_table(
     println!("{}", table);
 }
 
-pub fn display_checkpoints_with_tree(
-    root: &Path,
-    checkpoints: Vec<(String, String, Option<String>, i64, Option<String>)>,
-) {
+pub fn display_checkpoints_with_tree(root: &Path, checkpoints: Vec<CheckpointRow>) {
     println!("{}", "📁 VibeSnap Repository".bold().cyan());
 
     for (id, track, parent, timestamp, note) in checkpoints {
@@ -64,10 +62,10 @@ pub fn display_checkpoints_with_tree(
                 .dimmed()
         );
 
-        if let Some(note_text) = note {
-            if !note_text.is_empty() {
-                println!("{}   {}: {}", "│".blue(), "Note".cyan(), note_text);
-            }
+        if let Some(note_text) = note
+            && !note_text.is_empty()
+        {
+            println!("{}   {}: {}", "│".blue(), "Note".cyan(), note_text);
         }
 
         if let Some(parent_id) = parent {
@@ -87,7 +85,12 @@ pub fn display_checkpoints_with_tree(
 }
 
 pub fn display_file_tree(manifest: &SnapshotManifest, prefix: &str) {
-    let mut files: Vec<_> = manifest.files.keys().collect();
+    let mut files: Vec<String> = manifest
+        .files
+        .keys()
+        .cloned()
+        .chain(manifest.dirs.iter().map(|dir| format!("{}/", dir)))
+        .collect();
     files.sort();
 
     for (i, file_path) in files.iter().enumerate() {
@@ -136,3 +139,18 @@ pub fn show_side_by_side_diff(text1: &str, text2: &str, file_name: &str) {
         }
     }
 }
+
+/// Human-readable byte count, e.g. "1.5 MiB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -1,17 +1,20 @@
-use crate::commands::{diff::diff_command, restore::restore_command, switch::switch_command};
+use crate::commands::{
+    diff::diff_command,
+    restore::{RestoreOptions, restore_command},
+    switch::switch_command,
+};
 use crate::error::{AppError, Result};
 use crate::vibe::db::db_connect;
 use crate::vibe::repo::find_repo_root;
+use crate::vibe::revision::resolve_revision;
 use crate::vibe::snapshot::load_snapshot_manifest;
 use chrono::{Local, TimeZone};
 use dialoguer::{Select, theme::ColorfulTheme};
 use std::path::PathBuf;
 
-use super::display::display_file_tree;
+use super::display::{CheckpointRow, display_file_tree};
 
-pub fn interactive_list_selection(
-    checkpoints: Vec<(String, String, Option<String>, i64, Option<String>)>,
-) -> Result<()> {
+pub fn interactive_list_selection(checkpoints: Vec<CheckpointRow>) -> Result<()> {
     let root = find_repo_root(None)?;
     let items: Vec<String> = checkpoints
         .iter()
@@ -46,7 +49,14 @@ pub fn interactive_list_selection(
         .interact()?;
 
     match action {
-        0 => restore_command(selected_id.to_string(), true, None)?,
+        0 => restore_command(
+            selected_id.to_string(),
+            None,
+            RestoreOptions {
+                show_progress: true,
+                ..Default::default()
+            },
+        )?,
         1 => {
             if let Ok(manifest) = load_snapshot_manifest(&root, selected_id) {
                 println!("\nCheckpoint: {}", selected_id);
@@ -60,7 +70,7 @@ pub fn interactive_list_selection(
 }
 
 pub fn interactive_restore_command(
-    show_progress: bool,
+    options: RestoreOptions,
     selective_files: Option<Vec<PathBuf>>,
 ) -> Result<()> {
     let root = find_repo_root(None)?;
@@ -110,11 +120,15 @@ pub fn interactive_restore_command(
         .interact()?;
 
     let (selected_id, _, _, _, _) = &checkpoints[selection];
-    restore_command(selected_id.to_string(), show_progress, selective_files)
+    restore_command(selected_id.to_string(), selective_files, options)
 }
 
-pub fn interactive_file_restore_command(checkpoint_id: String, show_progress: bool) -> Result<()> {
+pub fn interactive_file_restore_command(
+    checkpoint_id: String,
+    options: RestoreOptions,
+) -> Result<()> {
     let root = find_repo_root(None)?;
+    let checkpoint_id = resolve_revision(&root, &db_connect(&root)?, &checkpoint_id)?;
     let manifest = load_snapshot_manifest(&root, &checkpoint_id)?;
 
     if manifest.files.is_empty() {
@@ -135,10 +149,10 @@ pub fn interactive_file_restore_command(checkpoint_id: String, show_progress: bo
         .interact()?;
 
     let selected_file = PathBuf::from(&file_list[selection]);
-    restore_command(checkpoint_id, show_progress, Some(vec![selected_file]))
+    restore_command(checkpoint_id, Some(vec![selected_file]), options)
 }
 
-pub fn interactive_switch_command() -> Result<()> {
+pub fn interactive_switch_command(options: RestoreOptions) -> Result<()> {
     let root = find_repo_root(None)?;
     let conn = db_connect(&root)?;
 
@@ -158,7 +172,7 @@ pub fn interactive_switch_command() -> Result<()> {
         .items(&tracks)
         .interact()?;
 
-    switch_command(tracks[selection].clone())
+    switch_command(tracks[selection].clone(), options)
 }
 
 pub fn interactive_diff_command(file_path_opt: Option<PathBuf>, side_by_side: bool) -> Result<()> {
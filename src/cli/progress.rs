@@ -1,16 +1,17 @@
 use crate::error::Result;
 use crate::vibe::{
+    entry::write_entry,
     objects::read_content_from_objects,
-    snapshot::{SnapshotManifest, build_snapshot_manifest},
+    snapshot::{BuildOptions, SnapshotManifest, build_snapshot_manifest},
 };
 use colored::*;
 use indicatif::{ProgressBar, ProgressIterator, ProgressStyle};
-use std::fs;
 use std::path::{Path, PathBuf};
 
 pub fn build_snapshot_manifest_with_progress(
     root: &Path,
     paths: &[PathBuf],
+    options: BuildOptions,
 ) -> Result<SnapshotManifest> {
     println!("{}", "Building snapshot...".cyan());
     let progress_bar = ProgressBar::new_spinner();
@@ -21,7 +22,7 @@ pub fn build_snapshot_manifest_with_progress(
     );
     progress_bar.set_message("Scanning files...");
 
-    let manifest = build_snapshot_manifest(root, paths)?;
+    let manifest = build_snapshot_manifest(root, paths, options)?;
 
     progress_bar.finish_with_message("Snapshot manifest built.");
     Ok(manifest)
@@ -42,13 +43,9 @@ pub fn restore_files_from_manifest_with_progress(
             .progress_chars("#>-"),
     );
 
-    for (file_path, hash) in manifest.files.iter().progress_with(progress_bar) {
-        let dest_path = root.join(file_path);
-        if let Some(parent_dir) = dest_path.parent() {
-            fs::create_dir_all(parent_dir)?;
-        }
-        let content = read_content_from_objects(root, hash)?;
-        fs::write(&dest_path, content)?;
+    for (file_path, entry) in manifest.files.iter().progress_with(progress_bar) {
+        let content = read_content_from_objects(root, &entry.hash)?;
+        write_entry(root, file_path, entry, content)?;
     }
 
     Ok(())
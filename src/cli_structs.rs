@@ -35,6 +35,36 @@ pub enum Commands {
         files: Vec<PathBuf>,
         #[clap(long, help = "Snap only the specified file")]
         file: Option<PathBuf>,
+        #[clap(
+            long,
+            help = "Re-read and hash every file instead of trusting the stat cache"
+        )]
+        rehash: bool,
+        #[clap(
+            short,
+            long,
+            help = "Number of files to hash and store in parallel (default: snapshot_jobs config, or one per CPU)"
+        )]
+        jobs: Option<usize>,
+        #[clap(long, help = "Create the checkpoint even if nothing changed")]
+        allow_empty: bool,
+        #[clap(
+            long,
+            help = "Record only the given paths instead of carrying the other files over from the parent checkpoint"
+        )]
+        only: bool,
+    },
+    /// Show files changed since the current checkpoint
+    Status {
+        #[clap(short, long, help = "Show changes in short porcelain format")]
+        short: bool,
+        #[clap(long, help = "Also list ignored files")]
+        ignored: bool,
+    },
+    /// Explain which ignore rule, if any, applies to each path
+    CheckIgnore {
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
     },
     /// List checkpoints
     List {
@@ -64,6 +94,18 @@ pub enum Commands {
         file: Option<PathBuf>,
         #[clap(long, help = "Interactive file selection within checkpoint")]
         interactive_files: bool,
+        #[clap(long, help = "Keep files that are not part of the checkpoint")]
+        keep_untracked: bool,
+        #[clap(
+            long,
+            help = "Discard unsnapped changes instead of saving a rescue checkpoint"
+        )]
+        force: bool,
+        #[clap(
+            long,
+            help = "Show what would be written and deleted without changing anything"
+        )]
+        dry_run: bool,
     },
     /// Create a new track
     Branch {
@@ -76,16 +118,33 @@ pub enum Commands {
         name: Option<String>,
         #[clap(long, help = "Interactive selection mode")]
         interactive: bool,
+        #[clap(long, help = "Keep files that are not part of the checkpoint")]
+        keep_untracked: bool,
+        #[clap(
+            long,
+            help = "Discard unsnapped changes instead of saving a rescue checkpoint"
+        )]
+        force: bool,
     },
     /// Restore the latest checkpoint on the current track
     Latest {
         #[clap(long, help = "Show progress bar for large operations")]
         progress: bool,
+        #[clap(long, help = "Keep files that are not part of the checkpoint")]
+        keep_untracked: bool,
+        #[clap(
+            long,
+            help = "Discard unsnapped changes instead of saving a rescue checkpoint"
+        )]
+        force: bool,
     },
-    /// Show unified diff between two checkpoints (text files)
+    /// Show unified diff between two checkpoints, or a checkpoint and the working tree
     Diff {
+        #[clap(help = "Checkpoint to diff from (defaults to HEAD)")]
         id1: Option<String>,
+        #[clap(help = "Checkpoint to diff to (defaults to the working tree)")]
         id2: Option<String>,
+        #[clap(long, help = "Limit the diff to a single file")]
         file: Option<PathBuf>,
         #[clap(long, help = "Show side-by-side diff view")]
         side_by_side: bool,
@@ -133,11 +192,25 @@ pub enum Commands {
         to: Option<String>,
         #[clap(long, help = "Show progress bar for large operations")]
         progress: bool,
+        #[clap(long, help = "Keep files that are not part of the checkpoint")]
+        keep_untracked: bool,
+        #[clap(
+            long,
+            help = "Discard unsnapped changes instead of saving a rescue checkpoint"
+        )]
+        force: bool,
     },
     /// Fast-forward to the next checkpoint in the timeline
     Fastforward {
         #[clap(long, help = "Show progress bar for large operations")]
         progress: bool,
+        #[clap(long, help = "Keep files that are not part of the checkpoint")]
+        keep_untracked: bool,
+        #[clap(
+            long,
+            help = "Discard unsnapped changes instead of saving a rescue checkpoint"
+        )]
+        force: bool,
     },
     /// Show a visual timeline of checkpoints
     Timeline {
@@ -146,6 +219,56 @@ pub enum Commands {
         #[clap(long, help = "Show detailed information")]
         detailed: bool,
     },
+    /// Show the log of operations that moved HEAD or track heads
+    Oplog {
+        #[clap(
+            short = 'n',
+            long,
+            default_value_t = 20,
+            help = "Number of operations to show"
+        )]
+        limit: usize,
+    },
+    /// Delete objects and manifests no checkpoint refers to
+    Gc {
+        #[clap(long, help = "Only report what would be removed")]
+        dry_run: bool,
+        #[clap(
+            long,
+            help = "Rewrite objects stored in the old uncompressed format with compression"
+        )]
+        recompress: bool,
+    },
+    /// Verify the integrity of objects, manifests, tracks and HEAD
+    Fsck {
+        #[clap(
+            long,
+            help = "Move corrupt objects and unreadable manifests into .vibe/quarantine"
+        )]
+        repair: bool,
+    },
+    /// Thin out old auto-snaps according to the retention policy
+    Prune {
+        #[clap(
+            long,
+            help = "Retention policy to apply instead of the configured one, e.g. 1h:all,1d:1h,30d:1d"
+        )]
+        policy: Option<String>,
+        #[clap(long, help = "Only list the auto-snaps that would be pruned")]
+        dry_run: bool,
+    },
+    /// Upgrade the repository to the current storage layout
+    Migrate,
+    /// Revert the last operation, including the working tree
+    Undo {
+        #[clap(long, help = "Discard unsnapped changes in the working tree")]
+        force: bool,
+    },
+    /// Reapply the last undone operation
+    Redo {
+        #[clap(long, help = "Discard unsnapped changes in the working tree")]
+        force: bool,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -2,10 +2,19 @@ use crate::error::{AppError, Result};
 use crate::vibe::{
     db::db_connect,
     repo::{find_repo_root, read_head},
+    revision::resolve_revision,
 };
 use colored::*;
 
 pub fn branch_command(name: String, from_id_opt: Option<String>) -> Result<()> {
+    // HEAD and the operation log store the track name space-separated
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(AppError::Generic(format!(
+            "Invalid track name '{}': it must not be empty or contain whitespace",
+            name
+        )));
+    }
+
     let root = find_repo_root(None)?;
     let conn = db_connect(&root)?;
 
@@ -15,7 +24,7 @@ pub fn branch_command(name: String, from_id_opt: Option<String>) -> Result<()> {
     }
 
     let from_checkpoint_id = match from_id_opt {
-        Some(id) => Some(id),
+        Some(id) => Some(resolve_revision(&root, &conn, &id)?),
         None => read_head(&root)?.1,
     };
 
@@ -25,12 +34,9 @@ pub fn branch_command(name: String, from_id_opt: Option<String>) -> Result<()> {
     )?;
 
     println!(
-        "{}",
-        format!(
-            "Created track {} at {}",
-            name.green(),
-            from_checkpoint_id.as_deref().unwrap_or("root").green()
-        )
+        "Created track {} at {}",
+        name.green(),
+        from_checkpoint_id.as_deref().unwrap_or("root").green()
     );
 
     Ok(())
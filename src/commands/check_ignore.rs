@@ -0,0 +1,60 @@
+use crate::error::Result;
+use crate::vibe::{
+    ignore::{is_internal_path, read_ignore_rules},
+    repo::find_repo_root,
+};
+use colored::*;
+use std::fs;
+use std::path::PathBuf;
+
+use super::snap::manifest_prefix;
+
+pub fn check_ignore_command(paths: Vec<PathBuf>) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let cwd = std::env::current_dir()?;
+    let matcher = read_ignore_rules(&root)?;
+
+    for path in paths {
+        // Resolved lexically, so `..` cannot climb out of the repo unnoticed
+        let Some(rel_path) = manifest_prefix(&root, &cwd.join(&path)) else {
+            println!(
+                "{}",
+                format!("{}: outside the repository", path.display()).yellow()
+            );
+            continue;
+        };
+        let is_dir = fs::symlink_metadata(root.join(&rel_path)).is_ok_and(|m| m.is_dir());
+
+        if is_internal_path(&rel_path) {
+            println!(
+                "{}\t{}",
+                "(built-in): .git and .vibe are always ignored".bright_black(),
+                rel_path.red()
+            );
+            continue;
+        }
+
+        match matcher.explain(&rel_path, is_dir) {
+            Some(rule) => {
+                let source = rule.source.strip_prefix(&root).unwrap_or(&rule.source);
+                let origin = format!("{}:{}:{}", source.display(), rule.line, rule.text);
+                if rule.is_negated() {
+                    println!(
+                        "{}\t{} (not ignored)",
+                        origin.bright_black(),
+                        rel_path.green()
+                    );
+                } else {
+                    println!("{}\t{}", origin.bright_black(), rel_path.red());
+                }
+            }
+            None => println!(
+                "{}\t{} (not ignored)",
+                "(no rule)".bright_black(),
+                rel_path.green()
+            ),
+        }
+    }
+
+    Ok(())
+}
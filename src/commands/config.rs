@@ -1,11 +1,11 @@
 use crate::config::Config;
-use crate::error::Result;
+use crate::error::{AppError, Result};
 use colored::*;
 
 pub fn config_command(action: crate::cli_structs::ConfigCommands) -> Result<()> {
     match action {
         crate::cli_structs::ConfigCommands::Show => {
-            let config = Config::load();
+            let config = Config::load()?;
             let config_str = toml::to_string_pretty(&config).unwrap();
             println!("{}", config_str);
         }
@@ -22,17 +22,14 @@ pub fn config_command(action: crate::cli_structs::ConfigCommands) -> Result<()>
                 .expect("Failed to open config file");
         }
         crate::cli_structs::ConfigCommands::Set { key, value } => {
-            let mut config = Config::load();
-            // This is a simplified way to set config. A real implementation would handle nested keys.
-            config
-                .extra
-                .insert(key.clone(), toml::Value::String(value.clone()));
+            let mut config = Config::load()?;
+            config.set_value(&key, &value).map_err(AppError::Generic)?;
             config.save()?;
             println!("Set {} = {}", key, value);
         }
         crate::cli_structs::ConfigCommands::Get { key } => {
-            let config = Config::load();
-            if let Some(value) = config.extra.get(&key) {
+            let config = Config::load()?;
+            if let Some(value) = config.get_value(&key) {
                 println!("{}", value);
             } else {
                 println!("Key not found");
@@ -1,11 +1,26 @@
 use crate::cli::display::{show_side_by_side_diff, show_unified_diff};
-use crate::error::Result;
+use crate::error::{AppError, Result};
 use crate::vibe::{
-    objects::read_content_from_objects, repo::find_repo_root, snapshot::load_snapshot_manifest,
+    db::db_connect,
+    entry::{FileEntry, FileKind, read_entry_content},
+    objects::read_content_from_objects,
+    repo::{find_repo_root, read_head},
+    revision::resolve_revision,
+    snapshot::{SnapshotManifest, load_snapshot_manifest, load_snapshot_tree},
+    status::scan_workspace,
+    tree::diff_trees,
 };
 use colored::*;
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where the contents of one side of a diff are read from
+#[derive(Clone, Copy)]
+enum DiffSource {
+    Objects,
+    Workdir,
+}
 
 pub fn diff_command(
     id1: String,
@@ -14,26 +29,88 @@ pub fn diff_command(
     side_by_side: bool,
 ) -> Result<()> {
     let root = find_repo_root(None)?;
-    let manifest1 = load_snapshot_manifest(&root, &id1)?;
-    let manifest2 = load_snapshot_manifest(&root, &id2)?;
+    let conn = db_connect(&root)?;
+    let id1 = resolve_revision(&root, &conn, &id1)?;
+    let id2 = resolve_revision(&root, &conn, &id2)?;
+    let trees = match file_path_opt {
+        // A single file needs the full manifests to tell a file that is the
+        // same on both sides from one that is on neither
+        Some(_) => None,
+        None => load_snapshot_tree(&root, &id1)?.zip(load_snapshot_tree(&root, &id2)?),
+    };
+    let (manifest1, manifest2) = match trees {
+        // Only the subtrees that differ are read
+        Some((tree1, tree2)) => diff_trees(&root, &tree1, &tree2)?,
+        None => (
+            load_snapshot_manifest(&root, &id1)?,
+            load_snapshot_manifest(&root, &id2)?,
+        ),
+    };
+
+    show_diff(
+        &root,
+        &manifest1,
+        &manifest2,
+        DiffSource::Objects,
+        file_path_opt,
+        side_by_side,
+    )
+}
+
+/// Diff a checkpoint (HEAD when `id` is `None`) against the files on disk
+pub fn diff_workdir_command(
+    id: Option<String>,
+    file_path_opt: Option<PathBuf>,
+    side_by_side: bool,
+) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let checkpoint_id = match id {
+        Some(id) => resolve_revision(&root, &db_connect(&root)?, &id)?,
+        None => read_head(&root)?.1.ok_or_else(|| {
+            AppError::Generic("HEAD has no checkpoint yet - nothing to diff against".to_string())
+        })?,
+    };
+    let manifest = load_snapshot_manifest(&root, &checkpoint_id)?;
+    let workspace = scan_workspace(&root, None)?;
 
+    show_diff(
+        &root,
+        &manifest,
+        &workspace,
+        DiffSource::Workdir,
+        file_path_opt,
+        side_by_side,
+    )
+}
+
+fn show_diff(
+    root: &Path,
+    manifest1: &SnapshotManifest,
+    manifest2: &SnapshotManifest,
+    source2: DiffSource,
+    file_path_opt: Option<PathBuf>,
+    side_by_side: bool,
+) -> Result<()> {
+    let (files1, files2) = (&manifest1.files, &manifest2.files);
     if let Some(relative_file_path) = file_path_opt {
         let path_str = relative_file_path.to_string_lossy();
-        let text1 = manifest1
-            .files
-            .get(path_str.as_ref())
-            .map(|hash| read_content_from_objects(&root, hash).unwrap_or_default())
-            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
-            .unwrap_or_default();
-        let text2 = manifest2
-            .files
-            .get(path_str.as_ref())
-            .map(|hash| read_content_from_objects(&root, hash).unwrap_or_default())
-            .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
-            .unwrap_or_default();
+        if !files1.contains_key(&*path_str) && !files2.contains_key(&*path_str) {
+            return Err(AppError::Generic(format!(
+                "{} is in neither side of the diff",
+                path_str
+            )));
+        }
+        let text1 = read_text(root, files1, &path_str, DiffSource::Objects);
+        let text2 = read_text(root, files2, &path_str, source2);
+        let type_change = describe_type_change(files1.get(&*path_str), files2.get(&*path_str));
 
+        if let Some(change) = &type_change {
+            println!("{}", change.cyan());
+        }
         if text1 == text2 {
-            println!("Files are identical.");
+            if type_change.is_none() {
+                println!("Files are identical.");
+            }
             return Ok(());
         }
 
@@ -43,28 +120,37 @@ pub fn diff_command(
             show_unified_diff(&text1, &text2, &path_str);
         }
     } else {
-        let files1: HashSet<_> = manifest1.files.keys().collect();
-        let files2: HashSet<_> = manifest2.files.keys().collect();
-        let all_files: Vec<_> = files1.union(&files2).collect();
+        for dir in &manifest1.dirs {
+            if !manifest2.has_dir(dir) {
+                println!("\n{}", format!("Directory removed: {}/", dir).red());
+            }
+        }
+        for dir in &manifest2.dirs {
+            if !manifest1.has_dir(dir) {
+                println!("\n{}", format!("Directory added: {}/", dir).green());
+            }
+        }
 
-        for file_path in all_files {
-            let hash1 = manifest1.files.get(*file_path);
-            let hash2 = manifest2.files.get(*file_path);
+        let all_files: BTreeSet<&String> = files1.keys().chain(files2.keys()).collect();
 
-            if hash1 == hash2 {
+        for file_path in all_files {
+            let (old, new) = (files1.get(file_path), files2.get(file_path));
+            if let (Some(old), Some(new)) = (old, new)
+                && !new.differs_from(old)
+            {
                 continue;
             }
 
             println!("\n{}", format!("Diff for {}:", file_path).bold());
+            if let Some(change) = describe_type_change(old, new) {
+                println!("{}", change.cyan());
+            }
+            if old.map(|e| &e.hash) == new.map(|e| &e.hash) {
+                continue;
+            }
 
-            let text1 = hash1
-                .map(|hash| read_content_from_objects(&root, hash).unwrap_or_default())
-                .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
-                .unwrap_or_default();
-            let text2 = hash2
-                .map(|hash| read_content_from_objects(&root, hash).unwrap_or_default())
-                .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
-                .unwrap_or_default();
+            let text1 = read_text(root, files1, file_path, DiffSource::Objects);
+            let text2 = read_text(root, files2, file_path, source2);
 
             if side_by_side {
                 show_side_by_side_diff(&text1, &text2, file_path);
@@ -76,3 +162,45 @@ pub fn diff_command(
 
     Ok(())
 }
+
+fn read_text(
+    root: &Path,
+    files: &BTreeMap<String, FileEntry>,
+    path: &str,
+    source: DiffSource,
+) -> String {
+    let bytes = match (files.get(path), source) {
+        (None, _) => return String::new(),
+        (Some(entry), DiffSource::Objects) => {
+            read_content_from_objects(root, &entry.hash).unwrap_or_default()
+        }
+        (Some(_), DiffSource::Workdir) => {
+            let full_path = root.join(path);
+            let metadata = fs::symlink_metadata(&full_path).ok();
+            read_entry_content(&full_path, metadata.as_ref()).unwrap_or_default()
+        }
+    };
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// A line such as "mode 644 -> 755" when a path changed type or permissions
+/// on both sides of the diff
+fn describe_type_change(old: Option<&FileEntry>, new: Option<&FileEntry>) -> Option<String> {
+    let (old, new) = (old?, new?);
+    // Compare as if both held the same content, so only type and mode count
+    let same_type = !FileEntry {
+        hash: old.hash.clone(),
+        ..new.clone()
+    }
+    .differs_from(old);
+    if same_type {
+        return None;
+    }
+    let describe = |entry: &FileEntry| match (entry.kind, entry.mode) {
+        (FileKind::Symlink, _) => "symlink".to_string(),
+        (_, Some(mode)) => format!("mode {:o}", mode),
+        (FileKind::Executable, None) => "executable file".to_string(),
+        (FileKind::Regular, None) => "regular file".to_string(),
+    };
+    Some(format!("{} -> {}", describe(old), describe(new)))
+}
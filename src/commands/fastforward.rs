@@ -5,7 +5,9 @@ use crate::vibe::{
 };
 use colored::*;
 
-pub fn fastforward_command(progress: bool) -> Result<()> {
+use super::restore::{RestoreOptions, restore_command};
+
+pub fn fastforward_command(options: RestoreOptions) -> Result<()> {
     let root = find_repo_root(None)?;
     let conn = db_connect(&root)?;
     let (current_track, current_checkpoint_id) = read_head(&root)?;
@@ -48,7 +50,7 @@ pub fn fastforward_command(progress: bool) -> Result<()> {
         format!("Fast-forwarding to checkpoint {} ...", next_checkpoint_id).cyan()
     );
 
-    crate::commands::restore::restore_command(next_checkpoint_id, progress, None)?;
+    restore_command(next_checkpoint_id, None, options)?;
 
     Ok(())
 }
@@ -0,0 +1,39 @@
+use crate::config::Config;
+use crate::error::{AppError, Result};
+use crate::vibe::{
+    db::db_connect,
+    fsck::{check_repository, quarantine},
+    repo::find_repo_root,
+};
+use colored::*;
+
+pub fn fsck_command(repair: bool) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let conn = db_connect(&root)?;
+
+    let problems = check_repository(&root, &conn, Config::load()?.snapshot_jobs())?;
+    let error_count = problems.iter().filter(|p| !p.is_warning()).count();
+
+    for problem in &problems {
+        if problem.is_warning() {
+            println!("{}", format!("warning: {}", problem).yellow());
+        } else {
+            println!("{}", format!("error: {}", problem).red());
+        }
+    }
+
+    if repair {
+        for path in quarantine(&root, &problems)? {
+            println!(
+                "{}",
+                format!("Quarantined {}", path.display()).bright_black()
+            );
+        }
+    }
+
+    if error_count > 0 {
+        return Err(AppError::IntegrityErrors(error_count));
+    }
+    println!("{}", "✓ Repository is consistent".green());
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use crate::cli::display::format_size;
+use crate::error::{AppError, Result};
+use crate::vibe::{
+    db::db_connect,
+    gc::{find_garbage, remove_garbage},
+    objects::{
+        ObjectEncoding, decode_object, encode_object, hash_file_content, list_objects,
+        read_object_encoding,
+    },
+    repo::find_repo_root,
+    utils::write_atomic,
+};
+use colored::*;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn gc_command(recompress: bool, dry_run: bool) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let conn = db_connect(&root)?;
+
+    let garbage = find_garbage(&root, &conn)?;
+    if garbage.is_empty() {
+        println!("No unreferenced objects or manifests.");
+    } else if dry_run {
+        for orphan in &garbage.manifests {
+            println!("Would remove manifest {}", orphan.name.yellow());
+        }
+        println!(
+            "Would remove {} unreferenced objects, {} orphaned manifests and {} stale temp files, reclaiming {}",
+            garbage.objects.len(),
+            garbage.manifests.len(),
+            garbage.temp_files.len(),
+            format_size(garbage.total_size()).cyan()
+        );
+    } else {
+        remove_garbage(&garbage)?;
+        println!(
+            "{}",
+            format!(
+                "✓ Removed {} unreferenced objects, {} orphaned manifests and {} stale temp files, reclaimed {}",
+                garbage.objects.len(),
+                garbage.manifests.len(),
+                garbage.temp_files.len(),
+                format_size(garbage.total_size())
+            )
+            .green()
+        );
+    }
+
+    // What is left after collection (or would be, for a dry run)
+    let collected: HashSet<&str> = garbage.objects.iter().map(|o| o.name.as_str()).collect();
+    let objects: Vec<(String, PathBuf)> = list_objects(&root)?
+        .into_iter()
+        .filter(|(hash, _)| !collected.contains(hash.as_str()))
+        .collect();
+
+    let mut legacy: Vec<(String, PathBuf)> = Vec::new();
+    let mut total_size: u64 = 0;
+    for (hash, path) in objects.iter() {
+        total_size += fs::metadata(path)?.len();
+        if read_object_encoding(hash, path)? == ObjectEncoding::Legacy {
+            legacy.push((hash.clone(), path.clone()));
+        }
+    }
+
+    println!(
+        "{} objects, {}",
+        objects.len(),
+        format_size(total_size).cyan()
+    );
+
+    if legacy.is_empty() {
+        return Ok(());
+    }
+    if !recompress {
+        println!(
+            "{}",
+            format!(
+                "{} objects use the old uncompressed format - run 'vibesnap gc --recompress' to convert them",
+                legacy.len()
+            )
+            .yellow()
+        );
+        return Ok(());
+    }
+    if dry_run {
+        println!("Would recompress {} objects", legacy.len());
+        return Ok(());
+    }
+
+    let mut converted = 0;
+    let mut saved: i64 = 0;
+    for (hash, path) in legacy {
+        match recompress_object(&hash, &path) {
+            Ok(delta) => {
+                converted += 1;
+                saved += delta;
+            }
+            Err(e) => eprintln!(
+                "{}",
+                format!("Warning: Could not recompress {} - {} - skipped", hash, e).yellow()
+            ),
+        }
+    }
+
+    println!(
+        "{}",
+        format!(
+            "✓ Recompressed {} objects, reclaimed {}",
+            converted,
+            format_size(saved.max(0) as u64)
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Rewrite a legacy object in the current format. The content is checked
+/// against its hash first so a damaged object is never made to look valid.
+/// Returns how many bytes the rewrite saved.
+fn recompress_object(hash: &str, path: &Path) -> Result<i64> {
+    let data = fs::read(path)?;
+    let content = decode_object(hash, &data)?;
+    if hash_file_content(&content) != hash {
+        return Err(AppError::CorruptObject(
+            hash.to_string(),
+            "content does not match its hash".to_string(),
+        ));
+    }
+
+    let encoded = encode_object(&content)?;
+    write_atomic(path, &encoded)?;
+    Ok(data.len() as i64 - encoded.len() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::{
+        constants::{OBJECTS_DIRNAME, REPO_DIRNAME},
+        objects::read_content_from_objects,
+    };
+
+    #[test]
+    fn recompress_rewrites_legacy_objects_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let objects_dir = root.join(REPO_DIRNAME).join(OBJECTS_DIRNAME);
+        fs::create_dir_all(&objects_dir).unwrap();
+        let content = "legacy line\n".repeat(100).into_bytes();
+        let hash = hash_file_content(&content);
+        fs::write(objects_dir.join(&hash), &content).unwrap();
+        fs::write(objects_dir.join("not-an-object"), "x").unwrap();
+
+        let objects = list_objects(root).unwrap();
+        assert_eq!(objects, vec![(hash.clone(), objects_dir.join(&hash))]);
+
+        let saved = recompress_object(&hash, &objects[0].1).unwrap();
+        assert!(saved > 0);
+        assert_eq!(
+            read_object_encoding(&hash, &objects_dir.join(&hash)).unwrap(),
+            ObjectEncoding::Zstd
+        );
+        assert_eq!(read_content_from_objects(root, &hash).unwrap(), content);
+    }
+
+    #[test]
+    fn recompress_refuses_objects_that_do_not_match_their_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("object");
+        fs::write(&path, "tampered").unwrap();
+        let hash = hash_file_content(b"original");
+
+        assert!(matches!(
+            recompress_object(&hash, &path),
+            Err(AppError::CorruptObject(..))
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"tampered");
+    }
+}
@@ -2,7 +2,7 @@ use crate::config::Config;
 use crate::error::{AppError, Result};
 use crate::vibe::{
     constants::{OBJECTS_DIRNAME, REPO_DIRNAME, SNAPSHOTS_DIRNAME},
-    db::{db_connect, ensure_schema},
+    db::db_connect,
     repo::write_head,
 };
 use colored::*;
@@ -10,7 +10,7 @@ use std::fs;
 use std::path::Path;
 
 pub fn init_command(path: &Path) -> Result<()> {
-    let config = Config::load();
+    let config = Config::load()?;
     let root = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
     let vibe_dir = root.join(REPO_DIRNAME);
 
@@ -24,7 +24,6 @@ pub fn init_command(path: &Path) -> Result<()> {
     fs::create_dir_all(vibe_dir.join(SNAPSHOTS_DIRNAME))?;
 
     let conn = db_connect(&root)?;
-    ensure_schema(&conn)?;
 
     // Use configured default track
     let default_track = config.get_default_track();
@@ -5,9 +5,9 @@ use crate::vibe::{
 };
 use colored::*;
 
-use super::restore::restore_command;
+use super::restore::{RestoreOptions, restore_command};
 
-pub fn latest_command(show_progress: bool) -> Result<()> {
+pub fn latest_command(options: RestoreOptions) -> Result<()> {
     let root = find_repo_root(None)?;
     let (track, _current_head) = read_head(&root)?;
 
@@ -20,14 +20,11 @@ pub fn latest_command(show_progress: bool) -> Result<()> {
 
     match latest_head_id {
         Ok(Some(head_id)) => {
-            restore_command(head_id.clone(), show_progress, None)?;
+            restore_command(head_id.clone(), None, options)?;
             println!(
-                "{}",
-                format!(
-                    "Restored latest checkpoint {} on track {}",
-                    head_id.green(),
-                    track.green()
-                )
+                "Restored latest checkpoint {} on track {}",
+                head_id.green(),
+                track.green()
             );
         }
         Ok(None) => {
@@ -1,11 +1,7 @@
 use crate::cli::display::{display_checkpoints_table, display_checkpoints_with_tree};
 use crate::cli::interactive::interactive_list_selection;
 use crate::error::Result;
-use crate::vibe::{
-    db::db_connect,
-    repo::find_repo_root,
-    snapshot::{SnapshotManifest, load_snapshot_manifest},
-};
+use crate::vibe::{db::db_connect, repo::find_repo_root};
 use colored::*;
 use std::path::PathBuf;
 
@@ -19,43 +15,35 @@ pub fn list_checkpoints_command(
     let conn = db_connect(&root)?;
 
     let mut query = "SELECT id, track, parent, timestamp, note FROM checkpoints".to_string();
+    let mut conditions: Vec<&str> = Vec::new();
     let mut params: Vec<String> = Vec::new();
 
     if let Some(track_name) = track_filter {
-        query.push_str(" WHERE track = ?1");
+        conditions.push("track = ?");
         params.push(track_name);
     }
+    if let Some(ref file_path) = file_filter {
+        conditions.push("id IN (SELECT checkpoint_id FROM checkpoint_files WHERE path = ?)");
+        params.push(file_path.to_string_lossy().to_string());
+    }
+    if !conditions.is_empty() {
+        query.push_str(" WHERE ");
+        query.push_str(&conditions.join(" AND "));
+    }
     query.push_str(" ORDER BY timestamp");
 
     let mut stmt = conn.prepare(&query)?;
-    let checkpoint_iter = stmt.query_map(rusqlite::params_from_iter(params), |row| {
-        Ok((
-            row.get::<_, String>(0)?,
-            row.get::<_, String>(1)?,
-            row.get::<_, Option<String>>(2)?,
-            row.get::<_, i64>(3)?,
-            row.get::<_, Option<String>>(4)?,
-        ))
-    })?;
-
-    let mut checkpoints = Vec::new();
-    for checkpoint_result in checkpoint_iter {
-        let checkpoint = checkpoint_result?;
-
-        if let Some(ref file_path) = file_filter {
-            let manifest: SnapshotManifest = match load_snapshot_manifest(&root, &checkpoint.0) {
-                Ok(m) => m,
-                Err(_) => continue,
-            };
-
-            let file_path_str = file_path.to_string_lossy().to_string();
-            if !manifest.files.contains_key(&file_path_str) {
-                continue;
-            }
-        }
-
-        checkpoints.push(checkpoint);
-    }
+    let checkpoints = stmt
+        .query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
 
     if checkpoints.is_empty() {
         if let Some(ref file_path) = file_filter {
@@ -0,0 +1,64 @@
+use crate::error::Result;
+use crate::vibe::{
+    constants::{REPO_DIRNAME, SNAPSHOTS_DIRNAME},
+    objects::{legacy_object_path, list_objects, object_path},
+    repo::find_repo_root,
+    snapshot::upgrade_snapshot_manifest,
+};
+use colored::*;
+use std::fs;
+
+/// Upgrade an existing repo to the current on-disk layout. Safe to run more
+/// than once; anything already migrated is left alone.
+pub fn migrate_command() -> Result<()> {
+    let root = find_repo_root(None)?;
+
+    let mut moved = 0;
+    for (hash, path) in list_objects(&root)? {
+        if path != legacy_object_path(&root, &hash) {
+            continue;
+        }
+        let target = object_path(&root, &hash);
+        if target.is_file() {
+            // Already stored in the new layout; the flat copy is redundant
+            fs::remove_file(&path)?;
+        } else {
+            if let Some(fan_dir) = target.parent() {
+                fs::create_dir_all(fan_dir)?;
+            }
+            fs::rename(&path, &target)?;
+        }
+        moved += 1;
+    }
+
+    let mut upgraded = 0;
+    let snapshots_dir = root.join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME);
+    if snapshots_dir.is_dir() {
+        for entry in fs::read_dir(&snapshots_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json")
+                && let Some(id) = path.file_stem().and_then(|stem| stem.to_str())
+                && upgrade_snapshot_manifest(&root, id)?
+            {
+                upgraded += 1;
+            }
+        }
+    }
+
+    if moved == 0 && upgraded == 0 {
+        println!("Repository is already up to date.");
+    }
+    if moved > 0 {
+        println!(
+            "{}",
+            format!("✓ Moved {} objects into the fanned-out layout", moved).green()
+        );
+    }
+    if upgraded > 0 {
+        println!(
+            "{}",
+            format!("✓ Rewrote {} manifests as tree objects", upgraded).green()
+        );
+    }
+    Ok(())
+}
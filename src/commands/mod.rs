@@ -1,17 +1,25 @@
 pub mod branch;
+pub mod check_ignore;
 pub mod config;
 pub mod diff;
 pub mod fastforward;
+pub mod fsck;
+pub mod gc;
 pub mod graph;
 pub mod init;
 pub mod latest;
 pub mod list;
+pub mod migrate;
+pub mod oplog;
+pub mod prune;
 pub mod reset;
 pub mod restore;
 pub mod rewind;
 pub mod snap;
+pub mod status;
 pub mod switch;
 pub mod timeline;
+pub mod undo;
 pub mod watch;
 
 // Helper function to combine --file and --files options
@@ -0,0 +1,62 @@
+use crate::error::Result;
+use crate::vibe::{db::db_connect, oplog::list_operations, repo::find_repo_root};
+use chrono::{Local, TimeZone};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Cell, ContentArrangement, Table};
+
+pub fn oplog_command(limit: usize) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let conn = db_connect(&root)?;
+    let operations = list_operations(&conn, limit)?;
+
+    if operations.is_empty() {
+        println!("No operations recorded yet.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "#",
+            "when",
+            "op",
+            "HEAD before",
+            "HEAD after",
+            "command",
+        ]);
+
+    for operation in operations {
+        let when = Local
+            .timestamp_opt(operation.timestamp, 0)
+            .single()
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let head = |track: &str, id: Option<&str>| format!("{} ({})", id.unwrap_or("root"), track);
+        let op_cell = if operation.undone {
+            Cell::new(format!("{} (undone)", operation.op)).fg(comfy_table::Color::Grey)
+        } else {
+            Cell::new(&operation.op).fg(comfy_table::Color::Cyan)
+        };
+
+        table.add_row(vec![
+            Cell::new(operation.id),
+            Cell::new(when),
+            op_cell,
+            Cell::new(head(
+                &operation.before.track,
+                operation.before.checkpoint_id.as_deref(),
+            )),
+            Cell::new(head(
+                &operation.after.track,
+                operation.after.checkpoint_id.as_deref(),
+            )),
+            Cell::new(operation.argv),
+        ]);
+    }
+
+    println!("{}", table);
+    Ok(())
+}
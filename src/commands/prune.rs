@@ -0,0 +1,60 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::vibe::{
+    db::db_connect,
+    repo::find_repo_root,
+    retention::{RetentionPolicy, apply_prune, plan_prune},
+};
+use chrono::{Local, TimeZone};
+use colored::*;
+
+pub fn prune_command(policy: Option<String>, dry_run: bool) -> Result<()> {
+    let config = Config::load()?;
+    let root = find_repo_root(None)?;
+    let mut conn = db_connect(&root)?;
+
+    let spec = policy.unwrap_or_else(|| config.retention_policy().to_string());
+    let policy = RetentionPolicy::parse(&spec)?;
+    let pruned = plan_prune(&root, &conn, &policy, chrono::Utc::now().timestamp())?;
+
+    if pruned.is_empty() {
+        println!("No auto-snaps to prune under policy {}", spec.cyan());
+        return Ok(());
+    }
+
+    for checkpoint in &pruned {
+        let when = Local
+            .timestamp_opt(checkpoint.timestamp, 0)
+            .single()
+            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        println!(
+            "{} {} {} [{}] {}",
+            if dry_run { "Would prune" } else { "Pruning" },
+            checkpoint.id.yellow(),
+            when.bright_black(),
+            checkpoint.track,
+            checkpoint.note.as_deref().unwrap_or_default()
+        );
+    }
+
+    if dry_run {
+        println!(
+            "Would prune {} auto-snaps under policy {}",
+            pruned.len(),
+            spec.cyan()
+        );
+        return Ok(());
+    }
+
+    apply_prune(&root, &mut conn, &pruned)?;
+    println!(
+        "{}",
+        format!(
+            "✓ Pruned {} auto-snaps. Run 'vibesnap gc' to reclaim their objects.",
+            pruned.len()
+        )
+        .green()
+    );
+    Ok(())
+}
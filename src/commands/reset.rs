@@ -10,7 +10,7 @@ pub fn reset_command(confirm: bool) -> Result<()> {
                 "This will irreversibly delete the .vibe directory and all snaps. Are you sure?",
             )
             .interact()
-            .map_err(|e| AppError::DialoguerError(e))?;
+            .map_err(AppError::DialoguerError)?;
 
         if !confirmation {
             println!("Reset cancelled.");
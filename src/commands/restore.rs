@@ -1,29 +1,51 @@
-use crate::error::Result;
+use crate::config::Config;
+use crate::error::{AppError, Result};
 use crate::vibe::{
+    checkpoint::{CheckpointKind, create_checkpoint},
+    db::db_connect,
+    entry::{create_dir_in_tree, write_entry},
     objects::read_content_from_objects,
+    oplog::{RepoState, record_operation},
     repo::{find_repo_root, read_head, write_head},
-    snapshot::{SnapshotManifest, load_snapshot_manifest},
+    revision::resolve_revision,
+    snapshot::{BuildOptions, SnapshotManifest, build_snapshot_manifest, load_snapshot_manifest},
+    status::{
+        list_empty_workspace_dirs, list_workspace_files, scan_workspace, status_against_head,
+    },
 };
 use colored::*;
-use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Flags shared by every command that restores a checkpoint into the working tree
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreOptions {
+    pub show_progress: bool,
+    /// Leave files that are absent from the target checkpoint in place
+    pub keep_untracked: bool,
+    /// Only report what would be written and deleted
+    pub dry_run: bool,
+    /// Overwrite unsnapped changes without a rescue checkpoint
+    pub force: bool,
+}
+
 pub fn restore_command(
     checkpoint_id: String,
-    show_progress: bool,
     selective_files: Option<Vec<PathBuf>>,
+    options: RestoreOptions,
 ) -> Result<()> {
     let root = find_repo_root(None)?;
+    let checkpoint_id = resolve_revision(&root, &db_connect(&root)?, &checkpoint_id)?;
     let manifest = load_snapshot_manifest(&root, &checkpoint_id)?;
     let (current_track_name, _) = read_head(&root)?;
-    let filtered_manifest = if let Some(ref files) = selective_files.as_ref() {
-        let mut filtered = SnapshotManifest {
-            files: HashMap::new(),
-        };
+    let filtered_manifest = if let Some(files) = selective_files.as_ref() {
+        let mut filtered = SnapshotManifest::default();
         for file_path in files.iter() {
             let file_path_str = file_path.to_string_lossy().to_string();
-            if let Some(hash) = manifest.files.get(&file_path_str) {
-                filtered.files.insert(file_path_str, hash.clone());
+            if let Some(entry) = manifest.files.get(&file_path_str) {
+                filtered.files.insert(file_path_str, entry.clone());
+            } else if manifest.dirs.contains(&file_path_str) {
+                filtered.dirs.insert(file_path_str);
             } else {
                 eprintln!(
                     "{}",
@@ -36,7 +58,7 @@ pub fn restore_command(
                 );
             }
         }
-        if filtered.files.is_empty() {
+        if filtered.files.is_empty() && filtered.dirs.is_empty() {
             eprintln!("{}", "No specified files found in checkpoint".red());
             return Ok(());
         }
@@ -44,11 +66,20 @@ pub fn restore_command(
     } else {
         manifest
     };
-    if show_progress {
-        crate::cli::progress::restore_files_from_manifest_with_progress(&root, &filtered_manifest)?;
-    } else {
-        restore_files_from_manifest(&root, &filtered_manifest)?;
+    // Only full restores sync the tree; selective restores never delete anything
+    let sync_untracked = selective_files.is_none() && !options.keep_untracked;
+    if options.dry_run {
+        return show_restore_plan(&root, &checkpoint_id, &filtered_manifest, sync_untracked);
+    }
+    if !options.force {
+        guard_unsnapped_changes(&root, &checkpoint_id, selective_files.as_deref())?;
     }
+    let removed = apply_manifest_to_workspace(
+        &root,
+        &filtered_manifest,
+        sync_untracked,
+        options.show_progress,
+    )?;
     if selective_files.is_none() {
         write_head(&root, &current_track_name, Some(&checkpoint_id))?;
     }
@@ -63,17 +94,20 @@ pub fn restore_command(
                 .collect::<Vec<_>>()
                 .join(", ")
         )
+    } else if !removed.is_empty() {
+        format!(
+            " ({} files, {} removed)",
+            filtered_manifest.files.len(),
+            removed.len()
+        )
     } else {
         format!(" ({} files)", filtered_manifest.files.len())
     };
     if selective_files.is_some() {
         println!(
-            "{}",
-            format!(
-                "Selective restore completed{}\nRestored files from checkpoint {} to workspace",
-                files_info,
-                checkpoint_id.green()
-            )
+            "Selective restore completed{}\nRestored files from checkpoint {} to workspace",
+            files_info,
+            checkpoint_id.green()
         );
     } else {
         let hint = format!(
@@ -82,29 +116,113 @@ pub fn restore_command(
             checkpoint_id
         );
         println!(
-            "{}",
-            format!(
-                "Workspace restored to {}{}\n{}: Workspace files now match '{}'.\nYou are still on track '{}', and HEAD pointer is updated to '{}' for parenting purposes.{}",
-                checkpoint_id.green(),
-                files_info,
-                "Detached mode".yellow(),
-                checkpoint_id,
-                current_track_name,
-                checkpoint_id,
-                hint
-            )
+            "Workspace restored to {}{}\n{}: Workspace files now match '{}'.\nYou are still on track '{}', and HEAD pointer is updated to '{}' for parenting purposes.{}",
+            checkpoint_id.green(),
+            files_info,
+            "Detached mode".yellow(),
+            checkpoint_id,
+            current_track_name,
+            checkpoint_id,
+            hint
         );
     }
     Ok(())
 }
-fn restore_files_from_manifest(root: &std::path::Path, manifest: &SnapshotManifest) -> Result<()> {
-    for (file_path, hash) in &manifest.files {
-        let dest_path = root.join(file_path);
-        if let Some(parent_dir) = dest_path.parent() {
-            fs::create_dir_all(parent_dir)?;
+/// Write every file and empty directory of `manifest` into the working tree
+/// and, when `sync_untracked` is set, delete the files and empty directories
+/// it does not contain. Returns the removed paths.
+///
+/// Deletions come first so a path that changed between file and directory
+/// (or a symlink standing where a directory goes) is out of the way before
+/// anything is written there.
+pub fn apply_manifest_to_workspace(
+    root: &Path,
+    manifest: &SnapshotManifest,
+    sync_untracked: bool,
+    show_progress: bool,
+) -> Result<Vec<String>> {
+    let removed = if sync_untracked {
+        remove_untracked_files(root, manifest)?
+    } else {
+        Vec::new()
+    };
+    if show_progress {
+        crate::cli::progress::restore_files_from_manifest_with_progress(root, manifest)?;
+    } else {
+        restore_files_from_manifest(root, manifest)?;
+    }
+    for dir in &manifest.dirs {
+        create_dir_in_tree(root, Path::new(dir))?;
+    }
+    Ok(removed)
+}
+
+/// Protect work that a restore would overwrite: depending on config, either
+/// take an auto "rescue" checkpoint of the working tree or refuse to continue.
+fn guard_unsnapped_changes(
+    root: &Path,
+    target_id: &str,
+    selective_files: Option<&[PathBuf]>,
+) -> Result<()> {
+    let status = status_against_head(root, false)?;
+
+    let change_count = match selective_files {
+        // A selective restore only overwrites the files it names
+        Some(files) => {
+            let selected: Vec<String> = files
+                .iter()
+                .map(|f| f.to_string_lossy().to_string())
+                .collect();
+            status
+                .added
+                .iter()
+                .chain(&status.modified)
+                .filter(|path| selected.contains(path))
+                .count()
         }
+        None => status.added.len() + status.modified.len() + status.deleted.len(),
+    };
+    if change_count == 0 {
+        return Ok(());
+    }
+
+    if !Config::load()?.rescue_snapshot_on_restore() {
+        return Err(AppError::DirtyWorkspace(change_count));
+    }
+
+    let (track, head_id) = read_head(root)?;
+    let conn = db_connect(root)?;
+    let before = RepoState::capture(root, &conn)?;
+    let manifest = build_snapshot_manifest(root, &[PathBuf::from(".")], BuildOptions::default())?;
+    let note = format!("Rescue snapshot before restoring {}", target_id);
+    let rescue_id = create_checkpoint(
+        root,
+        &conn,
+        &track,
+        head_id.as_deref(),
+        &manifest,
+        &note,
+        CheckpointKind::Rescue,
+    )?;
+    record_operation(root, &conn, "rescue", &before, false)?;
+    println!(
+        "{}",
+        format!(
+            "Saved {} unsnapped change(s) as rescue checkpoint {}",
+            change_count, rescue_id
+        )
+        .cyan()
+    );
+
+    Ok(())
+}
+
+fn restore_files_from_manifest(root: &Path, manifest: &SnapshotManifest) -> Result<()> {
+    for (file_path, entry) in &manifest.files {
+        let hash = &entry.hash;
+        let dest_path = root.join(file_path);
         match read_content_from_objects(root, hash) {
-            Ok(content) => match fs::write(&dest_path, content) {
+            Ok(content) => match write_entry(root, file_path, entry, content) {
                 Ok(_) => {}
                 Err(e) => {
                     eprintln!(
@@ -133,3 +251,232 @@ fn restore_files_from_manifest(root: &std::path::Path, manifest: &SnapshotManife
     }
     Ok(())
 }
+
+/// Delete files and empty directories in the working tree that are not part
+/// of `manifest`, then any directories left empty by those deletions. Ignored
+/// files are never touched.
+fn remove_untracked_files(root: &Path, manifest: &SnapshotManifest) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+
+    for rel_path in list_workspace_files(root, None)? {
+        if manifest.files.contains_key(&rel_path) {
+            continue;
+        }
+        let full_path = root.join(&rel_path);
+        match fs::remove_file(&full_path) {
+            Ok(_) => {
+                remove_empty_parents(root, &full_path);
+                removed.push(rel_path);
+            }
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Warning: Failed to remove {}. Error: {} - skipped",
+                        full_path.display(),
+                        e
+                    )
+                    .yellow()
+                );
+            }
+        }
+    }
+
+    for dir in list_empty_workspace_dirs(root)? {
+        if manifest.has_dir(&dir) {
+            continue;
+        }
+        let full_path = root.join(&dir);
+        if fs::remove_dir(&full_path).is_ok() {
+            remove_empty_parents(root, &full_path);
+            removed.push(format!("{}/", dir));
+        }
+    }
+
+    Ok(removed)
+}
+
+fn remove_empty_parents(root: &Path, path: &Path) {
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        // remove_dir fails on non-empty directories, which ends the climb
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        current = dir.parent();
+    }
+}
+
+fn show_restore_plan(
+    root: &Path,
+    checkpoint_id: &str,
+    manifest: &SnapshotManifest,
+    sync_untracked: bool,
+) -> Result<()> {
+    let workspace = scan_workspace(root, None)?;
+
+    // Directories are listed with a trailing slash
+    let mut to_write: Vec<String> = manifest
+        .files
+        .iter()
+        .filter(|(path, entry)| {
+            workspace
+                .files
+                .get(*path)
+                .is_none_or(|current| entry.differs_from(current))
+        })
+        .map(|(path, _)| path.clone())
+        .chain(
+            manifest
+                .dirs
+                .iter()
+                .filter(|dir| !workspace.has_dir(dir))
+                .map(|dir| format!("{}/", dir)),
+        )
+        .collect();
+    to_write.sort();
+
+    let mut to_delete: Vec<String> = if sync_untracked {
+        workspace
+            .files
+            .keys()
+            .filter(|path| !manifest.files.contains_key(*path))
+            .cloned()
+            .chain(
+                workspace
+                    .dirs
+                    .iter()
+                    .filter(|dir| !manifest.has_dir(dir))
+                    .map(|dir| format!("{}/", dir)),
+            )
+            .collect()
+    } else {
+        Vec::new()
+    };
+    to_delete.sort();
+
+    println!(
+        "{}",
+        format!("Dry run: restoring {} would", checkpoint_id).cyan()
+    );
+    if to_write.is_empty() && to_delete.is_empty() {
+        println!("  change nothing - workspace already matches");
+        return Ok(());
+    }
+    for path in &to_write {
+        println!("  {} {}", "write: ".green(), path);
+    }
+    for path in &to_delete {
+        println!("  {} {}", "delete:".red(), path);
+    }
+    println!(
+        "\n{} to write, {} to delete",
+        to_write.len(),
+        to_delete.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::constants::REPO_DIRNAME;
+    use crate::vibe::entry::FileEntry;
+    use crate::vibe::objects::store_content_in_objects;
+
+    fn write_file(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read_file(root: &Path, path: &str) -> Option<String> {
+        fs::read_to_string(root.join(path)).ok()
+    }
+
+    /// A repo whose working tree holds `files`
+    fn workspace(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        for (path, content) in files {
+            write_file(dir.path(), path, content);
+        }
+        dir
+    }
+
+    /// A manifest of `files`, with their content in the object store
+    fn stored_manifest(root: &Path, files: &[(&str, &str)]) -> SnapshotManifest {
+        SnapshotManifest {
+            files: files
+                .iter()
+                .map(|(path, content)| {
+                    let hash = store_content_in_objects(root, content.as_bytes()).unwrap();
+                    (path.to_string(), FileEntry::regular(hash))
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn full_restore_removes_untracked_files_and_their_empty_dirs() {
+        let dir = workspace(&[
+            ("a.txt", "changed"),
+            ("extra.txt", "x"),
+            ("old/deep/y.txt", "y"),
+            ("build/out.o", "o"),
+            (".vibeignore", "build/\n"),
+        ]);
+        let root = dir.path();
+        let manifest = stored_manifest(
+            root,
+            &[
+                ("a.txt", "original"),
+                ("src/lib.rs", "lib"),
+                (".vibeignore", "build/\n"),
+            ],
+        );
+
+        restore_files_from_manifest(root, &manifest).unwrap();
+        let mut removed = remove_untracked_files(root, &manifest).unwrap();
+        removed.sort();
+
+        assert_eq!(removed, vec!["extra.txt", "old/deep/y.txt"]);
+        assert_eq!(read_file(root, "a.txt").as_deref(), Some("original"));
+        assert_eq!(read_file(root, "src/lib.rs").as_deref(), Some("lib"));
+        assert!(!root.join("old").exists());
+        // Ignored files are never deleted
+        assert_eq!(read_file(root, "build/out.o").as_deref(), Some("o"));
+    }
+
+    #[test]
+    fn restore_without_sync_keeps_untracked_files() {
+        let dir = workspace(&[("a.txt", "changed"), ("extra.txt", "x")]);
+        let root = dir.path();
+        let manifest = stored_manifest(root, &[("a.txt", "original")]);
+
+        restore_files_from_manifest(root, &manifest).unwrap();
+
+        assert_eq!(read_file(root, "a.txt").as_deref(), Some("original"));
+        assert_eq!(read_file(root, "extra.txt").as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn empty_directories_are_recreated_and_untracked_ones_removed() {
+        let dir = workspace(&[("a.txt", "a")]);
+        let root = dir.path();
+        fs::create_dir_all(root.join("scratch/empty")).unwrap();
+        let mut manifest = stored_manifest(root, &[("a.txt", "a")]);
+        manifest.dirs.insert("logs/today".to_string());
+
+        let removed = apply_manifest_to_workspace(root, &manifest, true, false).unwrap();
+
+        assert_eq!(removed, vec!["scratch/empty/"]);
+        assert!(!root.join("scratch").exists());
+        assert!(root.join("logs/today").is_dir());
+    }
+}
@@ -2,14 +2,17 @@ use crate::error::{AppError, Result};
 use crate::vibe::{
     db::db_connect,
     repo::{find_repo_root, read_head},
+    utils::parse_duration,
 };
 use chrono::TimeZone;
 use colored::*;
 
+use super::restore::{RestoreOptions, restore_command};
+
 pub fn rewind_command(
     duration: Option<String>,
     to_time: Option<String>,
-    progress: bool,
+    options: RestoreOptions,
 ) -> Result<()> {
     let root = find_repo_root(None)?;
     let conn = db_connect(&root)?;
@@ -50,50 +53,11 @@ pub fn rewind_command(
         format!("Rewinding to checkpoint {} ...", checkpoint_id).cyan()
     );
 
-    crate::commands::restore::restore_command(checkpoint_id, progress, None)?;
+    restore_command(checkpoint_id, None, options)?;
 
     Ok(())
 }
 
-fn parse_duration(s: &str) -> Result<i64> {
-    let s = s.trim().to_lowercase();
-
-    // Try to parse formats like "30m", "2h", "1h30m", "45s"
-    let mut total_seconds = 0i64;
-    let mut current_num = String::new();
-
-    for ch in s.chars() {
-        if ch.is_ascii_digit() {
-            current_num.push(ch);
-        } else {
-            if current_num.is_empty() {
-                return Err(AppError::Generic(format!("Invalid duration format: {}", s)));
-            }
-
-            let num: i64 = current_num.parse().map_err(|_| {
-                AppError::Generic(format!("Invalid number in duration: {}", current_num))
-            })?;
-
-            let multiplier = match ch {
-                's' => 1,
-                'm' => 60,
-                'h' => 3600,
-                'd' => 86400,
-                _ => return Err(AppError::Generic(format!("Invalid time unit: {}", ch))),
-            };
-
-            total_seconds += num * multiplier;
-            current_num.clear();
-        }
-    }
-
-    if total_seconds == 0 {
-        return Err(AppError::Generic(format!("Invalid duration: {}", s)));
-    }
-
-    Ok(total_seconds)
-}
-
 fn parse_time_today(time_str: &str) -> Result<i64> {
     let today = chrono::Local::now().date_naive();
 
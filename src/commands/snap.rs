@@ -1,28 +1,42 @@
 use crate::config::Config;
 use crate::error::Result;
 use crate::vibe::{
+    checkpoint::{CheckpointKind, create_checkpoint, is_unchanged_from_parent},
     db::db_connect,
-    repo::{find_repo_root, read_head, write_head},
-    snapshot::{build_snapshot_manifest, save_snapshot_manifest},
-    utils::generate_id,
+    ignore::relative_path_string,
+    repo::{find_repo_root, read_head},
+    snapshot::{BuildOptions, build_snapshot_manifest, load_snapshot_manifest},
+    utils::canonicalize_keeping_link,
 };
 use colored::*;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+
+/// Flags controlling how `snap` builds its checkpoint
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SnapOptions {
+    pub show_progress: bool,
+    /// Ignore the stat cache and hash every file
+    pub rehash: bool,
+    /// Worker threads for hashing; `None` defers to the config
+    pub jobs: Option<usize>,
+    /// Create the checkpoint even if it matches its parent
+    pub allow_empty: bool,
+    /// Record only the given paths instead of overlaying them on the parent
+    pub only: bool,
+}
 
 pub fn snap_command(
     paths: Vec<PathBuf>,
     note: String,
-    show_progress: bool,
     selective_files: Option<Vec<PathBuf>>,
+    options: SnapOptions,
 ) -> Result<()> {
-    let config = Config::load();
+    let config = Config::load()?;
     let root = find_repo_root(None)?;
     let (track, parent_id) = read_head(&root)?;
 
     let conn = db_connect(&root)?;
 
-    let checkpoint_id = generate_id(&track);
-
     // Use auto-note if no note provided
     let final_note = if note.is_empty() {
         config.format_auto_note()
@@ -34,7 +48,7 @@ pub fn snap_command(
     let paths_to_process_input = match &selective_files {
         Some(files) => files.clone(),
         None => {
-            if paths.is_empty() || (paths.len() == 1 && paths[0] == PathBuf::from(".")) {
+            if paths.is_empty() || (paths.len() == 1 && paths[0] == Path::new(".")) {
                 vec![PathBuf::from(".")]
             } else {
                 paths
@@ -42,36 +56,58 @@ pub fn snap_command(
         }
     };
 
+    // Anything short of the whole tree is a partial snap, which by default
+    // carries the rest of the files over from the parent checkpoint
+    let is_partial = paths_to_process_input != [PathBuf::from(".")];
+
     // Use config to determine if progress should be shown
-    let should_show_progress = config.should_show_progress(show_progress);
+    let should_show_progress = config.should_show_progress(options.show_progress);
+
+    let build_options = BuildOptions {
+        rehash: options.rehash,
+        jobs: options.jobs.unwrap_or(0),
+    };
 
     // Build manifest with progress bar based on config
     let manifest = if should_show_progress {
-        crate::cli::progress::build_snapshot_manifest_with_progress(&root, &paths_to_process_input)?
+        crate::cli::progress::build_snapshot_manifest_with_progress(
+            &root,
+            &paths_to_process_input,
+            build_options,
+        )?
     } else {
-        build_snapshot_manifest(&root, &paths_to_process_input)?
+        build_snapshot_manifest(&root, &paths_to_process_input, build_options)?
     };
 
-    // Save the manifest
-    save_snapshot_manifest(&root, &checkpoint_id, &manifest)?;
-
-    conn.execute(
-        "INSERT INTO checkpoints(id, track, parent, timestamp, note) VALUES (?, ?, ?, ?, ?)",
-        rusqlite::params![
-            checkpoint_id,
-            track,
-            parent_id,
-            chrono::Utc::now().timestamp(),
-            final_note
-        ],
-    )?;
+    let manifest = match (&parent_id, is_partial && !options.only) {
+        (Some(parent_id), true) => {
+            let prefixes: Vec<String> = paths_to_process_input
+                .iter()
+                .filter_map(|path| manifest_prefix(&root, path))
+                .collect();
+            load_snapshot_manifest(&root, parent_id)?.overlay(manifest, &prefixes)
+        }
+        _ => manifest,
+    };
 
-    conn.execute(
-        "UPDATE tracks SET head = ? WHERE name = ?",
-        rusqlite::params![checkpoint_id, track],
-    )?;
+    if !options.allow_empty && is_unchanged_from_parent(&root, parent_id.as_deref(), &manifest) {
+        eprintln!(
+            "{}",
+            "Nothing changed since the last checkpoint - no snap created (use --allow-empty to snap anyway)"
+                .yellow()
+        );
+        return Ok(());
+    }
 
-    write_head(&root, &track, Some(&checkpoint_id))?;
+    let checkpoint_id = create_checkpoint(
+        &root,
+        &conn,
+        &track,
+        parent_id.as_deref(),
+        &manifest,
+        &final_note,
+        CheckpointKind::Manual,
+    )?;
 
     let file_count = manifest.files.len();
     let files_info = if let Some(ref selective) = selective_files {
@@ -95,3 +131,49 @@ pub fn snap_command(
 
     Ok(())
 }
+
+/// The manifest path a snap argument refers to, worked out lexically so that
+/// paths which no longer exist still cover their old entries. `None` if the
+/// path lies outside the repo.
+pub fn manifest_prefix(root: &Path, path: &Path) -> Option<String> {
+    let joined = root.join(path);
+    let joined = canonicalize_keeping_link(&joined).unwrap_or(joined);
+    let mut normalized = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    relative_path_string(&normalized, root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_prefix_resolves_paths_lexically() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+
+        let prefix = |path: &str| manifest_prefix(&root, Path::new(path));
+        assert_eq!(prefix("src"), Some("src".to_string()));
+        assert_eq!(
+            prefix("./src/../src/lib.rs"),
+            Some("src/lib.rs".to_string())
+        );
+        // Paths that no longer exist still map onto their old entries
+        assert_eq!(prefix("deleted/dir"), Some("deleted/dir".to_string()));
+        assert_eq!(prefix("."), Some(String::new()));
+        assert_eq!(prefix(".."), None);
+        assert_eq!(
+            prefix(root.join("src").to_str().unwrap()),
+            Some("src".to_string())
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::error::Result;
+use crate::vibe::{
+    repo::{find_repo_root, read_head},
+    status::status_against_head,
+};
+use colored::*;
+
+pub fn status_command(short: bool, show_ignored: bool) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let (track, head_id) = read_head(&root)?;
+    let status = status_against_head(&root, show_ignored)?;
+
+    if short {
+        for path in &status.added {
+            println!("A {}", path);
+        }
+        for path in &status.modified {
+            println!("M {}", path);
+        }
+        for path in &status.deleted {
+            println!("D {}", path);
+        }
+        for path in &status.ignored {
+            println!("! {}", path);
+        }
+        return Ok(());
+    }
+
+    println!(
+        "On track {}, HEAD at {}",
+        track.green(),
+        head_id
+            .as_deref()
+            .unwrap_or("root (no checkpoints yet)")
+            .green()
+    );
+
+    if status.is_clean() {
+        println!("{}", "Nothing changed since the last snap.".green());
+    } else {
+        println!("\nChanges since the last snap:");
+        for path in &status.added {
+            println!("  {} {}", "added:   ".green(), path.green());
+        }
+        for path in &status.modified {
+            println!("  {} {}", "modified:".yellow(), path.yellow());
+        }
+        for path in &status.deleted {
+            println!("  {} {}", "deleted: ".red(), path.red());
+        }
+        println!(
+            "\n{} added, {} modified, {} deleted",
+            status.added.len(),
+            status.modified.len(),
+            status.deleted.len()
+        );
+    }
+
+    if show_ignored && !status.ignored.is_empty() {
+        println!("\nIgnored files:");
+        for path in &status.ignored {
+            println!("  {}", path.bright_black());
+        }
+    }
+
+    Ok(())
+}
@@ -5,9 +5,9 @@ use crate::vibe::{
 };
 use colored::*;
 
-use super::restore::restore_command;
+use super::restore::{RestoreOptions, restore_command};
 
-pub fn switch_command(name: String) -> Result<()> {
+pub fn switch_command(name: String, options: RestoreOptions) -> Result<()> {
     let root = find_repo_root(None)?;
     let conn = db_connect(&root)?;
 
@@ -19,15 +19,12 @@ pub fn switch_command(name: String) -> Result<()> {
 
     match query_result {
         Ok(Some(head_id)) => {
-            restore_command(head_id.clone(), false, None)?;
+            restore_command(head_id.clone(), None, options)?;
             write_head(&root, &name, Some(&head_id))?;
             println!(
-                "{}",
-                format!(
-                    "Switched to track {} and restored checkpoint {}",
-                    name.green(),
-                    head_id.green()
-                )
+                "Switched to track {} and restored checkpoint {}",
+                name.green(),
+                head_id.green()
             );
             Ok(())
         }
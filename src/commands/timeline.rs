@@ -1,5 +1,6 @@
 use crate::error::Result;
 use crate::vibe::{
+    checkpoint::CheckpointKind,
     db::db_connect,
     repo::{find_repo_root, read_head},
 };
@@ -14,19 +15,19 @@ pub fn timeline_command(track: Option<String>, detailed: bool) -> Result<()> {
     let track_filter = track.unwrap_or(current_track.clone());
 
     let mut stmt = conn.prepare(
-        "SELECT id, timestamp, note, is_auto
+        "SELECT id, timestamp, note, kind
          FROM checkpoints
          WHERE track = ?
          ORDER BY timestamp ASC",
     )?;
 
-    let checkpoints: Vec<(String, i64, String, i64)> = stmt
+    let checkpoints: Vec<(String, i64, String, CheckpointKind)> = stmt
         .query_map(rusqlite::params![track_filter], |row| {
             Ok((
                 row.get::<_, String>(0)?,
                 row.get::<_, i64>(1)?,
                 row.get::<_, String>(2)?,
-                row.get::<_, i64>(3)?,
+                CheckpointKind::parse(&row.get::<_, String>(3)?),
             ))
         })?
         .collect::<std::result::Result<Vec<_>, _>>()?;
@@ -52,12 +53,12 @@ pub fn timeline_command(track: Option<String>, detailed: bool) -> Result<()> {
         table.load_preset(UTF8_FULL);
         table.set_header(vec!["Time", "ID", "Type", "Note"]);
 
-        for (id, timestamp, note, is_auto) in checkpoints {
+        for (id, timestamp, note, kind) in checkpoints {
             let dt = chrono::DateTime::from_timestamp(timestamp, 0)
                 .unwrap()
                 .format("%Y-%m-%d %H:%M:%S");
 
-            let checkpoint_type = if is_auto == 1 { "auto" } else { "manual" };
+            let checkpoint_type = kind.as_str();
             let marker = if Some(&id) == current_checkpoint_id.as_ref() {
                 "→"
             } else {
@@ -70,11 +71,11 @@ pub fn timeline_command(track: Option<String>, detailed: bool) -> Result<()> {
                 Cell::new(format!("  {}", id))
             };
 
-            let type_cell = if is_auto == 1 {
-                Cell::new(checkpoint_type).fg(comfy_table::Color::Grey)
-            } else {
-                Cell::new(checkpoint_type).fg(comfy_table::Color::Cyan)
-            };
+            let type_cell = Cell::new(checkpoint_type).fg(match kind {
+                CheckpointKind::Manual => comfy_table::Color::Cyan,
+                CheckpointKind::Auto => comfy_table::Color::Grey,
+                CheckpointKind::Rescue => comfy_table::Color::Yellow,
+            });
 
             table.add_row(vec![
                 Cell::new(dt.to_string()),
@@ -87,17 +88,19 @@ pub fn timeline_command(track: Option<String>, detailed: bool) -> Result<()> {
         println!("{}", table);
     } else {
         // Compact timeline view
-        for (id, timestamp, note, is_auto) in checkpoints {
+        for (id, timestamp, note, kind) in checkpoints {
             let dt = chrono::DateTime::from_timestamp(timestamp, 0)
                 .unwrap()
                 .format("%H:%M:%S");
 
             let marker = if Some(&id) == current_checkpoint_id.as_ref() {
                 "●".green()
-            } else if is_auto == 1 {
-                "○".bright_black()
             } else {
-                "◆".cyan()
+                match kind {
+                    CheckpointKind::Manual => "◆".cyan(),
+                    CheckpointKind::Auto => "○".bright_black(),
+                    CheckpointKind::Rescue => "◇".yellow(),
+                }
             };
 
             let time_display = format!("{}", dt).bright_black();
@@ -120,9 +123,10 @@ pub fn timeline_command(track: Option<String>, detailed: bool) -> Result<()> {
     println!();
     println!("{}", "Legend:".bright_black());
     println!(
-        "  {} Manual snap  {} Auto-snap  {} Current position",
+        "  {} Manual snap  {} Auto-snap  {} Rescue checkpoint  {} Current position",
         "◆".cyan(),
         "○".bright_black(),
+        "◇".yellow(),
         "●".green()
     );
     println!();
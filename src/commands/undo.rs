@@ -0,0 +1,229 @@
+use crate::error::{AppError, Result};
+use crate::vibe::{
+    db::db_connect,
+    oplog::{
+        Operation, RepoState, apply_state, first_undone_operation, last_active_operation,
+        set_undone, validate_state,
+    },
+    repo::find_repo_root,
+    snapshot::load_snapshot_manifest,
+    status::status_against_head,
+};
+use colored::*;
+use rusqlite::Connection;
+use std::path::Path;
+
+use super::restore::apply_manifest_to_workspace;
+
+pub fn undo_command(force: bool) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let conn = db_connect(&root)?;
+
+    let operation = last_active_operation(&conn)?
+        .ok_or_else(|| AppError::Generic("Nothing to undo".to_string()))?;
+    if operation.is_irreversible() {
+        return Err(AppError::Generic(format!(
+            "Cannot undo past {} #{} ({}): it cannot be reverted",
+            operation.op, operation.id, operation.argv
+        )));
+    }
+
+    move_to_state(&root, &conn, &operation, &operation.before, force)?;
+    set_undone(&conn, operation.id, true)?;
+
+    println!(
+        "{}",
+        format!(
+            "Undid {} #{} ({}). HEAD is now {}",
+            operation.op,
+            operation.id,
+            operation.argv,
+            describe_head(&operation.before)
+        )
+        .green()
+    );
+    Ok(())
+}
+
+pub fn redo_command(force: bool) -> Result<()> {
+    let root = find_repo_root(None)?;
+    let conn = db_connect(&root)?;
+
+    let operation = first_undone_operation(&conn)?
+        .ok_or_else(|| AppError::Generic("Nothing to redo".to_string()))?;
+
+    move_to_state(&root, &conn, &operation, &operation.after, force)?;
+    set_undone(&conn, operation.id, false)?;
+
+    println!(
+        "{}",
+        format!(
+            "Redid {} #{} ({}). HEAD is now {}",
+            operation.op,
+            operation.id,
+            operation.argv,
+            describe_head(&operation.after)
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Move HEAD and track heads to `state`, syncing the working tree as well when
+/// the operation being reverted or reapplied had rewritten it. Nothing is
+/// changed unless every checkpoint and manifest the move needs is there.
+fn move_to_state(
+    root: &Path,
+    conn: &Connection,
+    operation: &Operation,
+    state: &RepoState,
+    force: bool,
+) -> Result<()> {
+    validate_state(conn, state)?;
+    if operation.touches_workspace {
+        // Without a checkpoint there is no record of what the working tree
+        // held, so there is nothing to put back
+        let checkpoint_id = state.checkpoint_id.as_deref().ok_or_else(|| {
+            AppError::Generic(format!(
+                "Cannot return to that state: track {} had no checkpoint to restore the working tree from",
+                state.track
+            ))
+        })?;
+        let manifest = load_snapshot_manifest(root, checkpoint_id)?;
+        if !force {
+            let status = status_against_head(root, false)?;
+            if !status.is_clean() {
+                return Err(AppError::DirtyWorkspace(
+                    status.added.len() + status.modified.len() + status.deleted.len(),
+                ));
+            }
+        }
+        apply_manifest_to_workspace(root, &manifest, true, false)?;
+    }
+
+    apply_state(root, conn, state)
+}
+
+fn describe_head(state: &RepoState) -> String {
+    format!(
+        "{} on track {}",
+        state.checkpoint_id.as_deref().unwrap_or("root"),
+        state.track
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::checkpoint::{CheckpointKind, create_checkpoint};
+    use crate::vibe::constants::REPO_DIRNAME;
+    use crate::vibe::entry::FileEntry;
+    use crate::vibe::objects::store_content_in_objects;
+    use crate::vibe::oplog::{record_irreversible_operation, record_operation};
+    use crate::vibe::repo::{read_head, write_head};
+    use crate::vibe::snapshot::SnapshotManifest;
+    use std::fs;
+
+    /// Write `content` to a.txt and snap it on `main`
+    fn snap(root: &Path, conn: &Connection, content: &str) -> String {
+        fs::write(root.join("a.txt"), content).unwrap();
+        let hash = store_content_in_objects(root, content.as_bytes()).unwrap();
+        let manifest = SnapshotManifest {
+            files: [("a.txt".to_string(), FileEntry::regular(hash))].into(),
+            ..Default::default()
+        };
+        let parent = read_head(root).unwrap().1;
+        create_checkpoint(
+            root,
+            conn,
+            "main",
+            parent.as_deref(),
+            &manifest,
+            content,
+            CheckpointKind::Manual,
+        )
+        .unwrap()
+    }
+
+    /// A repo snapped as "one" then "two", followed by a journaled restore of
+    /// the first checkpoint
+    fn restored_repo() -> (tempfile::TempDir, Connection, Operation, [String; 2]) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        conn.execute("INSERT INTO tracks(name, head) VALUES ('main', NULL)", [])
+            .unwrap();
+        write_head(root, "main", None).unwrap();
+        let first = snap(root, &conn, "one");
+        let second = snap(root, &conn, "two");
+
+        let before = RepoState::capture(root, &conn).unwrap();
+        fs::write(root.join("a.txt"), "one").unwrap();
+        write_head(root, "main", Some(&first)).unwrap();
+        record_operation(root, &conn, "restore", &before, true).unwrap();
+        let operation = last_active_operation(&conn).unwrap().unwrap();
+        (dir, conn, operation, [first, second])
+    }
+
+    fn contents(root: &Path) -> String {
+        fs::read_to_string(root.join("a.txt")).unwrap()
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip_head_and_working_tree() {
+        let (dir, conn, operation, [first, second]) = restored_repo();
+        let root = dir.path();
+
+        move_to_state(root, &conn, &operation, &operation.before, false).unwrap();
+        assert_eq!(contents(root), "two");
+        assert_eq!(read_head(root).unwrap().1.as_deref(), Some(second.as_str()));
+        assert_eq!(RepoState::capture(root, &conn).unwrap(), operation.before);
+
+        move_to_state(root, &conn, &operation, &operation.after, false).unwrap();
+        assert_eq!(contents(root), "one");
+        assert_eq!(read_head(root).unwrap().1.as_deref(), Some(first.as_str()));
+        assert_eq!(RepoState::capture(root, &conn).unwrap(), operation.after);
+    }
+
+    #[test]
+    fn missing_checkpoint_is_refused_before_touching_files() {
+        let (dir, conn, operation, _) = restored_repo();
+        let root = dir.path();
+        let mut gone = operation.before.clone();
+        gone.checkpoint_id = Some("MISSING".to_string());
+
+        assert!(move_to_state(root, &conn, &operation, &gone, true).is_err());
+        assert_eq!(contents(root), "one");
+        assert_eq!(RepoState::capture(root, &conn).unwrap(), operation.after);
+    }
+
+    #[test]
+    fn state_without_checkpoint_is_refused_when_files_would_change() {
+        let (dir, conn, operation, _) = restored_repo();
+        let root = dir.path();
+        let mut empty = operation.before.clone();
+        empty.checkpoint_id = None;
+
+        assert!(move_to_state(root, &conn, &operation, &empty, true).is_err());
+        assert_eq!(contents(root), "one");
+        assert_eq!(RepoState::capture(root, &conn).unwrap(), operation.after);
+    }
+
+    #[test]
+    fn irreversible_operations_stop_undo_and_clear_redo() {
+        let (dir, conn, operation, _) = restored_repo();
+        let root = dir.path();
+        move_to_state(root, &conn, &operation, &operation.before, false).unwrap();
+        set_undone(&conn, operation.id, true).unwrap();
+
+        record_irreversible_operation(root, &conn, "prune").unwrap();
+        assert!(first_undone_operation(&conn).unwrap().is_none());
+        assert!(
+            last_active_operation(&conn)
+                .unwrap()
+                .unwrap()
+                .is_irreversible()
+        );
+    }
+}
@@ -1,19 +1,27 @@
 use crate::config::Config;
-use crate::error::Result;
+use crate::error::{AppError, Result};
 use crate::vibe::{
+    checkpoint::{CheckpointKind, create_checkpoint, is_unchanged_from_parent},
     db::db_connect,
-    ignore::read_ignore_patterns,
-    repo::{find_repo_root, read_head, write_head},
-    snapshot::{build_snapshot_manifest, save_snapshot_manifest},
-    utils::generate_id,
+    ignore::{is_ignore_file, read_ignore_rules, should_ignore_path},
+    lock::RepoLock,
+    oplog::{RepoState, record_irreversible_operation, record_operation},
+    repo::{find_repo_root, read_head},
+    retention::{RetentionPolicy, apply_prune, plan_prune},
+    snapshot::{BuildOptions, build_snapshot_manifest},
+    utils::is_process_running,
 };
 use colored::*;
 use notify_debouncer_full::{DebounceEventResult, new_debouncer, notify::*};
-use std::path::PathBuf;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::thread;
 use std::time::Duration;
 
+/// How long an auto-snap waits for another command to release the lock
+const WATCH_LOCK_WAIT: Duration = Duration::from_secs(10 * 60);
+
 pub fn watch_command(interval_minutes: Option<u64>, stop: bool, on_save: bool) -> Result<()> {
     let root = find_repo_root(None)?;
     let pid_file = root.join(".vibe").join("watch.pid");
@@ -22,7 +30,7 @@ pub fn watch_command(interval_minutes: Option<u64>, stop: bool, on_save: bool) -
         return stop_watch(&pid_file);
     }
 
-    let config = Config::load();
+    let config = Config::load()?;
     let interval = interval_minutes.unwrap_or_else(|| config.watch_interval_minutes());
 
     // Check if already running
@@ -80,17 +88,18 @@ pub fn watch_command(interval_minutes: Option<u64>, stop: bool, on_save: bool) -
     Ok(())
 }
 
-fn watch_on_interval(root: &PathBuf, interval: u64) -> Result<()> {
+fn watch_on_interval(root: &Path, interval: u64) -> Result<()> {
     loop {
         thread::sleep(Duration::from_secs(interval * 60));
 
         match create_auto_checkpoint(root, "⏱  Time-based") {
-            Ok(checkpoint_id) => {
+            Ok(Some(checkpoint_id)) => {
                 println!(
                     "{}",
                     format!("⏱  auto-snap {} created", checkpoint_id).bright_black()
                 );
             }
+            Ok(None) => {}
             Err(e) => {
                 eprintln!("{}", format!("Error creating auto-snap: {}", e).red());
             }
@@ -98,9 +107,8 @@ fn watch_on_interval(root: &PathBuf, interval: u64) -> Result<()> {
     }
 }
 
-fn watch_on_file_save(root: &PathBuf) -> Result<()> {
+fn watch_on_file_save(root: &Path) -> Result<()> {
     let (tx, rx) = channel();
-    let root_clone = root.clone();
 
     // Create debouncer with 2 second delay to batch multiple saves
     let mut debouncer = new_debouncer(
@@ -114,7 +122,7 @@ fn watch_on_file_save(root: &PathBuf) -> Result<()> {
 
     // Watch the root directory recursively
     debouncer
-        .watch(&root, RecursiveMode::Recursive)
+        .watch(root, RecursiveMode::Recursive)
         .map_err(|e| {
             crate::error::AppError::Generic(format!("Failed to watch directory: {}", e))
         })?;
@@ -122,30 +130,31 @@ fn watch_on_file_save(root: &PathBuf) -> Result<()> {
     println!("{}", "Watching for file changes...".cyan());
 
     // Load ignore patterns
-    let ignore_patterns = read_ignore_patterns(&root)?;
+    let mut ignore_rules = read_ignore_rules(root)?;
 
     loop {
         match rx.recv() {
             Ok(Ok(events)) => {
+                // Pick up edited ignore files before deciding what changed
+                let paths = || events.iter().flat_map(|event| &event.paths);
+                if paths().any(|path| is_ignore_file(path, root)) {
+                    match read_ignore_rules(root) {
+                        Ok(rules) => ignore_rules = rules,
+                        Err(e) => eprintln!(
+                            "{}",
+                            format!("Warning: Could not reload ignore rules: {}", e).yellow()
+                        ),
+                    }
+                }
+
                 // Filter out events in .vibe directory and ignored files
                 let mut has_relevant_change = false;
                 for event in events.iter() {
                     for path in &event.paths {
-                        if !path.starts_with(root.join(".vibe")) {
-                            // Check if the path matches ignore patterns
-                            let should_ignore = ignore_patterns.iter().any(|pattern| {
-                                if let Ok(glob) = glob::Pattern::new(pattern) {
-                                    if let Ok(rel_path) = path.strip_prefix(&root) {
-                                        return glob.matches_path(rel_path);
-                                    }
-                                }
-                                false
-                            });
-
-                            if !should_ignore {
-                                has_relevant_change = true;
-                                break;
-                            }
+                        // The same rules snapshots use, which also cover .vibe itself
+                        if !should_ignore_path(path, root, &ignore_rules) {
+                            has_relevant_change = true;
+                            break;
                         }
                     }
                     if has_relevant_change {
@@ -154,13 +163,14 @@ fn watch_on_file_save(root: &PathBuf) -> Result<()> {
                 }
 
                 if has_relevant_change {
-                    match create_auto_checkpoint(&root_clone, "💾 File save") {
-                        Ok(checkpoint_id) => {
+                    match create_auto_checkpoint(root, "💾 File save") {
+                        Ok(Some(checkpoint_id)) => {
                             println!(
                                 "{}",
                                 format!("💾 auto-snap {} created", checkpoint_id).bright_black()
                             );
                         }
+                        Ok(None) => {}
                         Err(e) => {
                             eprintln!("{}", format!("Error creating auto-snap: {}", e).red());
                         }
@@ -180,7 +190,7 @@ fn watch_on_file_save(root: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn stop_watch(pid_file: &PathBuf) -> Result<()> {
+fn stop_watch(pid_file: &Path) -> Result<()> {
     if !pid_file.exists() {
         println!("{}", "Watch is not running.".yellow());
         return Ok(());
@@ -203,57 +213,72 @@ fn stop_watch(pid_file: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn create_auto_checkpoint(root: &PathBuf, checkpoint_type: &str) -> Result<String> {
+/// Snap the whole working directory as an auto checkpoint. Returns `None`
+/// without writing anything when nothing changed since HEAD.
+fn create_auto_checkpoint(root: &Path, checkpoint_type: &str) -> Result<Option<String>> {
+    // Wait out a restore or snap in progress rather than capturing a
+    // half-written tree
+    let _lock = match RepoLock::acquire(root, "watch", Duration::ZERO) {
+        Ok(lock) => lock,
+        Err(AppError::RepoLocked(..)) => {
+            println!(
+                "{}",
+                "⏸  Repository busy - auto-snap paused until it is free".bright_black()
+            );
+            RepoLock::acquire(root, "watch", WATCH_LOCK_WAIT)?
+        }
+        Err(e) => return Err(e),
+    };
+
     let (track, parent_id) = read_head(root)?;
     let conn = db_connect(root)?;
-    let checkpoint_id = generate_id(&track);
 
     // Build manifest for entire working directory
-    let manifest = build_snapshot_manifest(root, &[PathBuf::from(".")])?;
+    let manifest = build_snapshot_manifest(root, &[PathBuf::from(".")], BuildOptions::default())?;
 
-    // Skip if no files
-    if manifest.files.is_empty() {
-        return Ok(checkpoint_id);
+    // Skip if there are no files or they all match HEAD
+    if manifest.files.is_empty() || is_unchanged_from_parent(root, parent_id.as_deref(), &manifest)
+    {
+        return Ok(None);
     }
 
-    save_snapshot_manifest(root, &checkpoint_id, &manifest)?;
-
     let note = format!(
         "{} at {}",
         checkpoint_type,
         chrono::Local::now().format("%H:%M:%S")
     );
 
-    conn.execute(
-        "INSERT INTO checkpoints(id, track, parent, timestamp, note, is_auto) VALUES (?, ?, ?, ?, ?, 1)",
-        rusqlite::params![
-            checkpoint_id,
-            track,
-            parent_id,
-            chrono::Utc::now().timestamp(),
-            note
-        ],
-    )?;
-
-    conn.execute(
-        "UPDATE tracks SET head = ? WHERE name = ?",
-        rusqlite::params![checkpoint_id, track],
+    let before = RepoState::capture(root, &conn)?;
+    let checkpoint_id = create_checkpoint(
+        root,
+        &conn,
+        &track,
+        parent_id.as_deref(),
+        &manifest,
+        &note,
+        CheckpointKind::Auto,
     )?;
+    record_operation(root, &conn, "auto-snap", &before, false)?;
 
-    write_head(root, &track, Some(&checkpoint_id))?;
-
-    Ok(checkpoint_id)
-}
+    let config = Config::load()?;
+    if config.prune_on_watch()
+        && let Err(e) = prune_auto_checkpoints(root, conn, config.retention_policy())
+    {
+        eprintln!("{}", format!("Error pruning auto-snaps: {}", e).red());
+    }
 
-#[cfg(unix)]
-fn is_process_running(pid: i32) -> bool {
-    unsafe { libc::kill(pid, 0) == 0 }
+    Ok(Some(checkpoint_id))
 }
 
-#[cfg(not(unix))]
-fn is_process_running(_pid: i32) -> bool {
-    // On non-Unix systems, assume it's running
-    true
+/// Apply the retention policy after an auto-snap, silently
+fn prune_auto_checkpoints(root: &Path, mut conn: Connection, policy: &str) -> Result<()> {
+    let policy = RetentionPolicy::parse(policy)?;
+    let pruned = plan_prune(root, &conn, &policy, chrono::Utc::now().timestamp())?;
+    if pruned.is_empty() {
+        return Ok(());
+    }
+    apply_prune(root, &mut conn, &pruned)?;
+    record_irreversible_operation(root, &conn, "prune")
 }
 
 #[cfg(unix)]
@@ -276,3 +301,33 @@ fn kill_process(_pid: i32) -> Result<()> {
         "Process killing not supported on this platform".to_string(),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::{constants::REPO_DIRNAME, repo::write_head};
+    use std::fs;
+
+    #[test]
+    fn auto_snap_is_skipped_when_nothing_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        write_head(&root, "main", None).unwrap();
+        db_connect(&root)
+            .unwrap()
+            .execute("INSERT INTO tracks(name, head) VALUES ('main', NULL)", [])
+            .unwrap();
+        fs::write(root.join("a.txt"), "one").unwrap();
+
+        let first = create_auto_checkpoint(&root, "test").unwrap();
+        assert!(first.is_some());
+        assert_eq!(read_head(&root).unwrap().1, first);
+        assert_eq!(create_auto_checkpoint(&root, "test").unwrap(), None);
+
+        fs::write(root.join("a.txt"), "two").unwrap();
+        let second = create_auto_checkpoint(&root, "test").unwrap();
+        assert!(second.is_some() && second != first);
+        assert_eq!(read_head(&root).unwrap().1, second);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::{AppError, Result};
+use crate::vibe::retention::DEFAULT_RETENTION_POLICY;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -17,6 +19,16 @@ pub struct Config {
     watch_interval_minutes: u64,
     #[serde(default)]
     watch_enabled: bool,
+    #[serde(default = "default_true")]
+    rescue_snapshot_on_restore: bool,
+    #[serde(default)]
+    snapshot_jobs: usize,
+    #[serde(default = "default_retention_policy")]
+    retention_policy: String,
+    #[serde(default)]
+    prune_on_watch: bool,
+    #[serde(default = "default_lock_timeout")]
+    lock_timeout_seconds: u64,
     #[serde(flatten)]
     pub extra: HashMap<String, toml::Value>,
 }
@@ -25,6 +37,18 @@ fn default_watch_interval() -> u64 {
     5
 }
 
+fn default_lock_timeout() -> u64 {
+    10
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_retention_policy() -> String {
+    DEFAULT_RETENTION_POLICY.to_string()
+}
+
 fn default_user() -> String {
     "anonymous".to_string()
 }
@@ -46,6 +70,11 @@ impl Default for Config {
             default_track: default_track_name(),
             watch_interval_minutes: default_watch_interval(),
             watch_enabled: false,
+            rescue_snapshot_on_restore: default_true(),
+            snapshot_jobs: 0,
+            retention_policy: default_retention_policy(),
+            prune_on_watch: false,
+            lock_timeout_seconds: default_lock_timeout(),
             extra: HashMap::new(),
         }
     }
@@ -59,16 +88,26 @@ impl Config {
             .join("config.toml")
     }
 
-    pub fn load() -> Self {
+    /// User-global ignore file, applied beneath every repo's own ignore files
+    pub fn global_ignore_path() -> PathBuf {
+        Self::config_path().with_file_name("ignore")
+    }
+
+    /// Read the config file, or the defaults when there is none. A file that
+    /// does not parse is an error rather than silently replaced by defaults,
+    /// which would drop every other setting in it.
+    pub fn load() -> Result<Self> {
         let path = Self::config_path();
         if !path.exists() {
-            return Config::default();
+            return Ok(Config::default());
         }
-        let content = fs::read_to_string(path).unwrap_or_default();
-        toml::from_str(&content).unwrap_or_default()
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).map_err(|e| {
+            AppError::InvalidConfig(path.display().to_string(), e.message().to_string())
+        })
     }
 
-    pub fn save(&self) -> Result<(), std::io::Error> {
+    pub fn save(&self) -> std::io::Result<()> {
         let path = Self::config_path();
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
@@ -95,4 +134,110 @@ impl Config {
     pub fn watch_interval_minutes(&self) -> u64 {
         self.watch_interval_minutes
     }
+
+    /// Whether a restore over unsnapped changes first takes an auto checkpoint
+    /// (`true`) or refuses to run (`false`)
+    pub fn rescue_snapshot_on_restore(&self) -> bool {
+        self.rescue_snapshot_on_restore
+    }
+
+    /// Worker threads used to hash and store files during a snap; 0 means
+    /// one per available CPU
+    pub fn snapshot_jobs(&self) -> usize {
+        self.snapshot_jobs
+    }
+
+    /// Tiers deciding which auto-snaps `prune` keeps, see `RetentionPolicy::parse`
+    pub fn retention_policy(&self) -> &str {
+        &self.retention_policy
+    }
+
+    /// Whether the watcher applies the retention policy after each auto-snap
+    pub fn prune_on_watch(&self) -> bool {
+        self.prune_on_watch
+    }
+
+    /// How long a command waits for another one holding the repository lock
+    pub fn lock_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lock_timeout_seconds)
+    }
+
+    /// Set a key by round-tripping through TOML, so known settings get typed
+    /// values and anything else lands in `extra`. The string is read as the
+    /// type the key already has, so `user = 123` stays a string; only keys
+    /// not set yet have their type guessed.
+    pub fn set_value(&mut self, key: &str, value: &str) -> std::result::Result<(), String> {
+        let mut table = toml::Table::try_from(&*self).map_err(|e| e.to_string())?;
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid value for {}: {}", key, e);
+        let parsed = match table.get(key) {
+            Some(toml::Value::String(_)) => toml::Value::String(value.to_string()),
+            Some(toml::Value::Boolean(_)) => {
+                toml::Value::Boolean(value.parse().map_err(|e| invalid(&e))?)
+            }
+            Some(toml::Value::Integer(_)) => {
+                toml::Value::Integer(value.parse().map_err(|e| invalid(&e))?)
+            }
+            Some(toml::Value::Float(_)) => {
+                toml::Value::Float(value.parse().map_err(|e| invalid(&e))?)
+            }
+            _ => value
+                .parse::<bool>()
+                .map(toml::Value::Boolean)
+                .or_else(|_| value.parse::<i64>().map(toml::Value::Integer))
+                .unwrap_or_else(|_| toml::Value::String(value.to_string())),
+        };
+        table.insert(key.to_string(), parsed);
+        *self = table
+            .try_into()
+            .map_err(|e: toml::de::Error| format!("Invalid value for {}: {}", key, e.message()))?;
+        Ok(())
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<toml::Value> {
+        toml::Table::try_from(self).ok()?.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_value_uses_the_type_of_the_key() {
+        let mut config = Config::default();
+        config.set_value("user", "123").unwrap();
+        config.set_value("default_track", "2024").unwrap();
+        config.set_value("show_progress", "true").unwrap();
+        config.set_value("snapshot_jobs", "4").unwrap();
+        assert_eq!(config.user, "123");
+        assert_eq!(config.get_default_track(), "2024");
+        assert!(config.should_show_progress(false));
+        assert_eq!(config.snapshot_jobs(), 4);
+
+        assert!(config.set_value("show_progress", "yes").is_err());
+        assert!(config.set_value("snapshot_jobs", "-1").is_err());
+        assert!(config.set_value("lock_timeout_seconds", "soon").is_err());
+    }
+
+    #[test]
+    fn set_value_guesses_the_type_of_new_keys() {
+        let mut config = Config::default();
+        config.set_value("custom_flag", "true").unwrap();
+        config.set_value("custom_count", "7").unwrap();
+        config.set_value("custom_name", "x").unwrap();
+        assert_eq!(
+            config.get_value("custom_flag"),
+            Some(toml::Value::Boolean(true))
+        );
+        assert_eq!(
+            config.get_value("custom_count"),
+            Some(toml::Value::Integer(7))
+        );
+        // Once set, later values keep that type
+        config.set_value("custom_name", "8").unwrap();
+        assert_eq!(
+            config.get_value("custom_name"),
+            Some(toml::Value::String("8".to_string()))
+        );
+    }
 }
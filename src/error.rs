@@ -14,6 +14,14 @@ pub enum AppError {
     InvalidHead,
     #[error("Object not found in store: {0}")]
     ObjectNotFound(String),
+    #[error("Object {0} is unreadable: {1}")]
+    CorruptObject(String, String),
+    #[error("No checkpoint matches '{0}'")]
+    CheckpointNotFound(String),
+    #[error("Checkpoint prefix '{0}' is ambiguous. Candidates:\n{1}")]
+    AmbiguousCheckpoint(String, String),
+    #[error("Invalid revision '{0}': {1}")]
+    InvalidRevision(String, String),
     #[error("Snapshot manifest not found for {0}")]
     ManifestNotFound(String),
     #[error("Could not serialize manifest: {0}")]
@@ -26,6 +34,26 @@ pub enum AppError {
     TrackNotFound(String),
     #[error("Need at least two checkpoints to diff")]
     NotEnoughCheckpointsForDiff,
+    #[error(
+        "Working tree has {0} unsnapped change(s). Snap them first or pass --force to discard them."
+    )]
+    DirtyWorkspace(usize),
+    #[error(
+        "Repository is locked by {0}. Try again once it finishes, or remove {1} if it is stuck."
+    )]
+    RepoLocked(String, String),
+    #[error(
+        "Repository database is at schema version {0}, but this vibesnap only understands up to version {1}. Upgrade vibesnap to use this repository."
+    )]
+    SchemaTooNew(u32, u32),
+    #[error("Config file {0} is invalid: {1}")]
+    InvalidConfig(String, String),
+    #[error(
+        "Checkpoint(s) {0} have no manifest, so gc cannot tell which objects they still need. Run 'vibesnap fsck' and restore or delete them first."
+    )]
+    CheckpointsWithoutManifest(String),
+    #[error("Repository check found {0} problem(s)")]
+    IntegrityErrors(usize),
     #[error("Dialoguer error: {0}")]
     DialoguerError(#[from] dialoguer::Error),
     #[error("{0}")]
@@ -7,9 +7,16 @@ mod vibe;
 
 use clap::Parser;
 use colored::*;
+use config::Config;
 use error::Result;
 
 use cli_structs::{Cli, Commands, SelectCommands};
+use commands::restore::RestoreOptions;
+use commands::snap::SnapOptions;
+use std::path::Path;
+use vibe::checkpoint::Recovery;
+use vibe::lock::RepoLock;
+use vibe::oplog::OperationTracker;
 
 fn main() {
     if let Err(e) = run() {
@@ -21,7 +28,133 @@ fn main() {
 fn run() -> Result<()> {
     let cli = Cli::parse();
 
-    match cli.command {
+    let root = vibe::repo::find_repo_root(None).ok();
+    let lock = match (&root, lock_name(&cli.command)) {
+        (Some(root), Some(name)) => Some(RepoLock::acquire(
+            root,
+            name,
+            Config::load()?.lock_timeout(),
+        )?),
+        _ => None,
+    };
+
+    if let Some(root) = &root {
+        // Opening the database upgrades its schema, or stops every command
+        // here if a newer vibesnap created it
+        vibe::db::db_connect(root)?;
+        recover_interrupted_snap(root, lock.is_some())?;
+    }
+
+    let journal = journal_kind(&cli.command).and_then(|(op, touches_workspace)| {
+        let root = root.clone()?;
+        let conn = vibe::db::db_connect(&root).ok()?;
+        let tracker = OperationTracker::begin(&root, &conn, op, touches_workspace).ok()?;
+        Some((root, tracker))
+    });
+
+    let irreversible = irreversible_kind(&cli.command);
+
+    dispatch(cli.command)?;
+
+    if let Some((root, tracker)) = journal {
+        let conn = vibe::db::db_connect(&root)?;
+        tracker.finish(&root, &conn)?;
+    }
+    if let (Some(root), Some(op)) = (&root, irreversible) {
+        let conn = vibe::db::db_connect(root)?;
+        vibe::oplog::record_irreversible_operation(root, &conn, op)?;
+    }
+    Ok(())
+}
+
+/// Operation name for commands that can move HEAD or track heads, and whether
+/// they rewrite the working tree along the way
+fn journal_kind(command: &Commands) -> Option<(&'static str, bool)> {
+    match command {
+        Commands::Snap { .. } => Some(("snap", false)),
+        Commands::Restore { files, file, .. } => {
+            Some(("restore", files.is_empty() && file.is_none()))
+        }
+        Commands::List {
+            interactive: true, ..
+        } => Some(("restore", true)),
+        Commands::Branch { .. } => Some(("branch", false)),
+        Commands::Switch { .. } => Some(("switch", true)),
+        Commands::Latest { .. } => Some(("latest", true)),
+        Commands::Rewind { .. } => Some(("rewind", true)),
+        Commands::Fastforward { .. } => Some(("fastforward", true)),
+        Commands::Select { action } => match action {
+            SelectCommands::Restore { .. } => Some(("restore", true)),
+            SelectCommands::Switch => Some(("switch", true)),
+            SelectCommands::Diff { .. } => None,
+        },
+        _ => None,
+    }
+}
+
+/// Operation name for commands that delete history or rewrite storage, which
+/// undo must not reach back across. `reset` is left out because it deletes
+/// the operation log along with the rest of the repo.
+fn irreversible_kind(command: &Commands) -> Option<&'static str> {
+    match command {
+        Commands::Prune { dry_run: false, .. } => Some("prune"),
+        Commands::Gc { dry_run: false, .. } => Some("gc"),
+        Commands::Migrate => Some("migrate"),
+        Commands::Fsck { repair: true } => Some("fsck --repair"),
+        _ => None,
+    }
+}
+
+/// Name recorded in the repository lock for commands that modify the repo or
+/// the working tree; `None` for read-only commands
+fn lock_name(command: &Commands) -> Option<&'static str> {
+    if let Some((op, _)) = journal_kind(command) {
+        return Some(op);
+    }
+    match command {
+        Commands::Undo { .. } => Some("undo"),
+        Commands::Redo { .. } => Some("redo"),
+        Commands::Gc { .. } => Some("gc"),
+        Commands::Prune { .. } => Some("prune"),
+        Commands::Migrate => Some("migrate"),
+        Commands::Fsck { repair: true } => Some("fsck --repair"),
+        Commands::Reset { .. } => Some("reset"),
+        _ => None,
+    }
+}
+
+/// Finish or roll back a snap that was cut short last time, before any
+/// command looks at HEAD. Read-only commands take the lock just for this.
+fn recover_interrupted_snap(root: &Path, holds_lock: bool) -> Result<()> {
+    if !vibe::checkpoint::has_pending_checkpoint(root) {
+        return Ok(());
+    }
+    let _lock = if holds_lock {
+        None
+    } else {
+        Some(RepoLock::acquire(
+            root,
+            "recovery",
+            Config::load()?.lock_timeout(),
+        )?)
+    };
+    let conn = vibe::db::db_connect(root)?;
+    match vibe::checkpoint::recover_interrupted_checkpoint(root, &conn)? {
+        Some(Recovery::Completed(id)) => eprintln!(
+            "{}",
+            format!("Recovered interrupted snap {}: HEAD now points to it", id).yellow()
+        ),
+        Some(Recovery::Discarded(id)) => eprintln!(
+            "{}",
+            format!("Discarded interrupted snap {} that was never recorded", id).yellow()
+        ),
+        None => {}
+    }
+    Ok(())
+}
+
+fn dispatch(command: Commands) -> Result<()> {
+    match command {
         Commands::Init { path } => commands::init::init_command(&path)?,
         Commands::Snap {
             paths,
@@ -29,10 +162,27 @@ fn run() -> Result<()> {
             progress,
             files,
             file,
+            rehash,
+            jobs,
+            allow_empty,
+            only,
         } => {
             let selective_files = commands::get_selective_files(files, file);
-            commands::snap::snap_command(paths, note, progress, selective_files)?
+            commands::snap::snap_command(
+                paths,
+                note,
+                selective_files,
+                SnapOptions {
+                    show_progress: progress,
+                    rehash,
+                    jobs,
+                    allow_empty,
+                    only,
+                },
+            )?
         }
+        Commands::Status { short, ignored } => commands::status::status_command(short, ignored)?,
+        Commands::CheckIgnore { paths } => commands::check_ignore::check_ignore_command(paths)?,
         Commands::List {
             track,
             tree,
@@ -46,50 +196,78 @@ fn run() -> Result<()> {
             files,
             file,
             interactive_files,
+            keep_untracked,
+            dry_run,
+            force,
         } => {
             let selective_files = commands::get_selective_files(files, file);
-            if interactive || checkpoint_id.is_none() {
-                cli::interactive::interactive_restore_command(progress, selective_files)?
-            } else if interactive_files {
-                cli::interactive::interactive_file_restore_command(
-                    checkpoint_id.unwrap(),
-                    progress,
-                )?
-            } else {
-                commands::restore::restore_command(
-                    checkpoint_id.unwrap(),
-                    progress,
-                    selective_files,
-                )?
+            let options = RestoreOptions {
+                show_progress: progress,
+                keep_untracked,
+                dry_run,
+                force,
+            };
+            match checkpoint_id {
+                Some(checkpoint_id) if !interactive => {
+                    if interactive_files {
+                        cli::interactive::interactive_file_restore_command(checkpoint_id, options)?
+                    } else {
+                        commands::restore::restore_command(checkpoint_id, selective_files, options)?
+                    }
+                }
+                _ => cli::interactive::interactive_restore_command(options, selective_files)?,
             }
         }
         Commands::Branch { name, from_id } => commands::branch::branch_command(name, from_id)?,
-        Commands::Switch { name, interactive } => {
-            if interactive || name.is_none() {
-                cli::interactive::interactive_switch_command()?
-            } else {
-                commands::switch::switch_command(name.unwrap())?
+        Commands::Switch {
+            name,
+            interactive,
+            keep_untracked,
+            force,
+        } => {
+            let options = RestoreOptions {
+                keep_untracked,
+                force,
+                ..Default::default()
+            };
+            match name {
+                Some(name) if !interactive => commands::switch::switch_command(name, options)?,
+                _ => cli::interactive::interactive_switch_command(options)?,
             }
         }
-        Commands::Latest { progress } => commands::latest::latest_command(progress)?,
+        Commands::Latest {
+            progress,
+            keep_untracked,
+            force,
+        } => commands::latest::latest_command(RestoreOptions {
+            show_progress: progress,
+            keep_untracked,
+            force,
+            ..Default::default()
+        })?,
         Commands::Diff {
             id1,
             id2,
             file,
             side_by_side,
             interactive,
-        } => {
-            if interactive || id1.is_none() || id2.is_none() {
-                cli::interactive::interactive_diff_command(file, side_by_side)?
-            } else {
-                commands::diff::diff_command(id1.unwrap(), id2.unwrap(), file, side_by_side)?
-            }
-        }
+        } => match (id1, id2) {
+            _ if interactive => cli::interactive::interactive_diff_command(file, side_by_side)?,
+            (Some(id1), Some(id2)) => commands::diff::diff_command(id1, id2, file, side_by_side)?,
+            (id1, None) => commands::diff::diff_workdir_command(id1, file, side_by_side)?,
+            (None, Some(_)) => unreachable!("clap fills positional arguments in order"),
+        },
         Commands::Select { action } => match action {
-            SelectCommands::Restore { progress } => {
-                cli::interactive::interactive_restore_command(progress, None)?
+            SelectCommands::Restore { progress } => cli::interactive::interactive_restore_command(
+                RestoreOptions {
+                    show_progress: progress,
+                    ..Default::default()
+                },
+                None,
+            )?,
+            SelectCommands::Switch => {
+                cli::interactive::interactive_switch_command(RestoreOptions::default())?
             }
-            SelectCommands::Switch => cli::interactive::interactive_switch_command()?,
             SelectCommands::Diff { side_by_side } => {
                 cli::interactive::interactive_diff_command(None, side_by_side)?
             }
@@ -110,11 +288,41 @@ fn run() -> Result<()> {
             duration,
             to,
             progress,
-        } => commands::rewind::rewind_command(duration, to, progress)?,
-        Commands::Fastforward { progress } => commands::fastforward::fastforward_command(progress)?,
+            keep_untracked,
+            force,
+        } => commands::rewind::rewind_command(
+            duration,
+            to,
+            RestoreOptions {
+                show_progress: progress,
+                keep_untracked,
+                force,
+                ..Default::default()
+            },
+        )?,
+        Commands::Fastforward {
+            progress,
+            keep_untracked,
+            force,
+        } => commands::fastforward::fastforward_command(RestoreOptions {
+            show_progress: progress,
+            keep_untracked,
+            force,
+            ..Default::default()
+        })?,
         Commands::Timeline { track, detailed } => {
             commands::timeline::timeline_command(track, detailed)?
         }
+        Commands::Oplog { limit } => commands::oplog::oplog_command(limit)?,
+        Commands::Gc {
+            dry_run,
+            recompress,
+        } => commands::gc::gc_command(recompress, dry_run)?,
+        Commands::Fsck { repair } => commands::fsck::fsck_command(repair)?,
+        Commands::Prune { policy, dry_run } => commands::prune::prune_command(policy, dry_run)?,
+        Commands::Migrate => commands::migrate::migrate_command()?,
+        Commands::Undo { force } => commands::undo::undo_command(force)?,
+        Commands::Redo { force } => commands::undo::redo_command(force)?,
     }
     Ok(())
 }
@@ -0,0 +1,364 @@
+use crate::error::{AppError, Result};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::constants::{PENDING_CHECKPOINT_FILENAME, REPO_DIRNAME, SNAPSHOTS_DIRNAME};
+use super::objects::hash_file_content;
+use super::repo::write_head;
+use super::snapshot::{SnapshotManifest, load_snapshot_manifest, save_snapshot_manifest};
+use super::utils::{generate_id, is_process_running, write_atomic};
+
+/// Contents of the PENDING marker: the checkpoint being created and the
+/// process creating it
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingCheckpoint {
+    track: String,
+    checkpoint: String,
+    pid: i32,
+}
+
+/// What happened to a checkpoint whose creation was interrupted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// The database had it, so HEAD was moved onto it
+    Completed(String),
+    /// It never reached the database, so its manifest was removed
+    Discarded(String),
+}
+
+/// Why a checkpoint was made. Only watcher auto-snaps are thinned out by
+/// the retention policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointKind {
+    /// Made by `snap`
+    Manual,
+    /// Made by the watcher
+    Auto,
+    /// Saved by `restore` so unsnapped changes it overwrites are not lost
+    Rescue,
+}
+
+impl CheckpointKind {
+    /// The value stored in the `kind` column
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckpointKind::Manual => "manual",
+            CheckpointKind::Auto => "auto",
+            CheckpointKind::Rescue => "rescue",
+        }
+    }
+
+    /// Read a `kind` column value; anything unrecognised counts as manual,
+    /// the kind retention never touches
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "auto" => CheckpointKind::Auto,
+            "rescue" => CheckpointKind::Rescue,
+            _ => CheckpointKind::Manual,
+        }
+    }
+}
+
+/// Record `manifest` as a new checkpoint on `track`, advance the track head and
+/// point HEAD at it. Returns the new checkpoint ID.
+///
+/// The steps are ordered so a crash at any point is recoverable: a PENDING
+/// marker names the checkpoint first, the manifest is written atomically, the
+/// database rows change in a single transaction, HEAD is renamed into place
+/// last and the marker is then removed. `recover_interrupted_checkpoint`
+/// finishes or rolls back whatever the marker still names.
+pub fn create_checkpoint(
+    root: &Path,
+    conn: &Connection,
+    track: &str,
+    parent_id: Option<&str>,
+    manifest: &SnapshotManifest,
+    note: &str,
+    kind: CheckpointKind,
+) -> Result<String> {
+    let manifest_json = serde_json::to_vec(manifest)
+        .map_err(|e| AppError::ManifestSerializationError(e.to_string()))?;
+    let checkpoint_id = generate_id(track, parent_id, &hash_file_content(&manifest_json));
+
+    let pending = PendingCheckpoint {
+        track: track.to_string(),
+        checkpoint: checkpoint_id.clone(),
+        pid: std::process::id() as i32,
+    };
+    write_atomic(
+        &pending_path(root),
+        &serde_json::to_vec(&pending)
+            .map_err(|e| AppError::Generic(format!("Failed to write PENDING marker: {}", e)))?,
+    )?;
+    save_snapshot_manifest(root, &checkpoint_id, manifest)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO checkpoints(id, track, parent, timestamp, note, is_auto, kind) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![
+            checkpoint_id,
+            track,
+            parent_id,
+            chrono::Utc::now().timestamp(),
+            note,
+            (kind == CheckpointKind::Auto) as i64,
+            kind.as_str()
+        ],
+    )?;
+    record_checkpoint_files(&tx, &checkpoint_id, manifest)?;
+    tx.execute(
+        "UPDATE tracks SET head = ? WHERE name = ?",
+        rusqlite::params![checkpoint_id, track],
+    )?;
+    tx.commit()?;
+
+    write_head(root, track, Some(&checkpoint_id))?;
+    fs::remove_file(pending_path(root))?;
+
+    Ok(checkpoint_id)
+}
+
+/// Add the files of `manifest` to the `checkpoint_files` table, which lets
+/// path lookups such as `list --file` skip loading manifests
+pub fn record_checkpoint_files(
+    conn: &Connection,
+    checkpoint_id: &str,
+    manifest: &SnapshotManifest,
+) -> Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO checkpoint_files(checkpoint_id, path, hash) VALUES (?, ?, ?)",
+    )?;
+    for (path, entry) in &manifest.files {
+        stmt.execute(rusqlite::params![checkpoint_id, path, entry.hash])?;
+    }
+    Ok(())
+}
+
+fn pending_path(root: &Path) -> PathBuf {
+    root.join(REPO_DIRNAME).join(PENDING_CHECKPOINT_FILENAME)
+}
+
+/// Whether a PENDING marker says a checkpoint may have been left half-created
+pub fn has_pending_checkpoint(root: &Path) -> bool {
+    pending_path(root).exists()
+}
+
+/// Finish or roll back a checkpoint left half-created by a crash, as named
+/// by the PENDING marker. Returns `None` when there was nothing to recover
+/// or the process that wrote the marker is still running.
+pub fn recover_interrupted_checkpoint(root: &Path, conn: &Connection) -> Result<Option<Recovery>> {
+    let pending = pending_path(root);
+    let Ok(content) = fs::read_to_string(&pending) else {
+        return Ok(None);
+    };
+    // A marker cut short before it was renamed into place cannot exist, so
+    // anything unparsable is not ours to interpret
+    let PendingCheckpoint {
+        track,
+        checkpoint: checkpoint_id,
+        pid,
+    } = serde_json::from_str(&content).map_err(|_| {
+        AppError::Generic(format!(
+            "Unrecognised {} file; remove {} if no snap is running",
+            PENDING_CHECKPOINT_FILENAME,
+            pending.display()
+        ))
+    })?;
+
+    // Still being written by a live process, e.g. the watcher
+    if pid != std::process::id() as i32 && is_process_running(pid) {
+        return Ok(None);
+    }
+
+    let committed: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM checkpoints WHERE id = ?)",
+        [&checkpoint_id],
+        |row| row.get(0),
+    )?;
+
+    let recovery = if committed {
+        write_head(root, &track, Some(&checkpoint_id))?;
+        Recovery::Completed(checkpoint_id)
+    } else {
+        let manifest_path = root
+            .join(REPO_DIRNAME)
+            .join(SNAPSHOTS_DIRNAME)
+            .join(format!("{}.json", checkpoint_id));
+        if manifest_path.exists() {
+            fs::remove_file(manifest_path)?;
+        }
+        Recovery::Discarded(checkpoint_id)
+    };
+    fs::remove_file(pending)?;
+    Ok(Some(recovery))
+}
+
+/// Whether `manifest` records exactly the same files and directories as the
+/// parent checkpoint (or is empty when there is no parent), i.e. snapping it
+/// would be a no-op
+pub fn is_unchanged_from_parent(
+    root: &Path,
+    parent_id: Option<&str>,
+    manifest: &SnapshotManifest,
+) -> bool {
+    match parent_id {
+        Some(parent_id) => {
+            load_snapshot_manifest(root, parent_id).is_ok_and(|parent| parent == *manifest)
+        }
+        None => manifest.files.is_empty() && manifest.dirs.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::db::db_connect;
+    use crate::vibe::entry::FileEntry;
+    use crate::vibe::repo::read_head;
+    use std::collections::BTreeMap;
+
+    fn manifest(files: &[(&str, &str)]) -> SnapshotManifest {
+        SnapshotManifest {
+            files: files
+                .iter()
+                .map(|(path, hash)| (path.to_string(), FileEntry::regular(hash.to_string())))
+                .collect::<BTreeMap<_, _>>(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unchanged_only_when_files_match_the_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let parent = manifest(&[("a.txt", "h1"), ("b.txt", "h2")]);
+        save_snapshot_manifest(root, "PARENT", &parent).unwrap();
+
+        assert!(is_unchanged_from_parent(root, Some("PARENT"), &parent));
+        let edited = manifest(&[("a.txt", "h1"), ("b.txt", "h3")]);
+        assert!(!is_unchanged_from_parent(root, Some("PARENT"), &edited));
+        let removed = manifest(&[("a.txt", "h1")]);
+        assert!(!is_unchanged_from_parent(root, Some("PARENT"), &removed));
+        // A parent whose manifest is gone can't be compared against
+        assert!(!is_unchanged_from_parent(root, Some("MISSING"), &parent));
+    }
+
+    #[test]
+    fn without_a_parent_only_an_empty_manifest_is_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_unchanged_from_parent(dir.path(), None, &manifest(&[])));
+        assert!(!is_unchanged_from_parent(
+            dir.path(),
+            None,
+            &manifest(&[("a.txt", "h1")])
+        ));
+    }
+
+    fn write_marker(root: &Path, track: &str, checkpoint_id: &str, pid: i32) {
+        let pending = PendingCheckpoint {
+            track: track.to_string(),
+            checkpoint: checkpoint_id.to_string(),
+            pid,
+        };
+        fs::write(pending_path(root), serde_json::to_vec(&pending).unwrap()).unwrap();
+    }
+
+    /// A repo with a `main` track and a PENDING marker left by a process
+    /// that is no longer running
+    fn interrupted_repo(checkpoint_id: &str) -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        conn.execute("INSERT INTO tracks(name, head) VALUES ('main', NULL)", [])
+            .unwrap();
+        write_head(root, "main", None).unwrap();
+        save_snapshot_manifest(root, checkpoint_id, &manifest(&[("a.txt", "h1")])).unwrap();
+        write_marker(root, "main", checkpoint_id, i32::MAX);
+        (dir, conn)
+    }
+
+    #[test]
+    fn committed_checkpoint_is_completed() {
+        let (dir, conn) = interrupted_repo("MAIN000000000001");
+        let root = dir.path();
+        conn.execute(
+            "INSERT INTO checkpoints(id, track) VALUES ('MAIN000000000001', 'main')",
+            [],
+        )
+        .unwrap();
+
+        assert_eq!(
+            recover_interrupted_checkpoint(root, &conn).unwrap(),
+            Some(Recovery::Completed("MAIN000000000001".to_string()))
+        );
+        assert_eq!(
+            read_head(root).unwrap(),
+            ("main".to_string(), Some("MAIN000000000001".to_string()))
+        );
+        assert!(!pending_path(root).exists());
+        assert_eq!(recover_interrupted_checkpoint(root, &conn).unwrap(), None);
+    }
+
+    #[test]
+    fn uncommitted_checkpoint_is_discarded() {
+        let (dir, conn) = interrupted_repo("MAIN000000000001");
+        let root = dir.path();
+
+        assert_eq!(
+            recover_interrupted_checkpoint(root, &conn).unwrap(),
+            Some(Recovery::Discarded("MAIN000000000001".to_string()))
+        );
+        assert!(load_snapshot_manifest(root, "MAIN000000000001").is_err());
+        assert_eq!(read_head(root).unwrap(), ("main".to_string(), None));
+        assert!(!pending_path(root).exists());
+    }
+
+    #[test]
+    fn marker_of_a_running_snap_is_left_alone() {
+        let (dir, conn) = interrupted_repo("MAIN000000000001");
+        let root = dir.path();
+        // Our own parent process is certainly still alive
+        let live_pid = std::os::unix::process::parent_id();
+        write_marker(root, "main", "MAIN000000000001", live_pid as i32);
+
+        assert_eq!(recover_interrupted_checkpoint(root, &conn).unwrap(), None);
+        assert!(pending_path(root).exists());
+    }
+
+    #[test]
+    fn unparsable_marker_is_reported() {
+        let (dir, conn) = interrupted_repo("MAIN000000000001");
+        let root = dir.path();
+        fs::write(pending_path(root), "main MAIN000000000001 1\n").unwrap();
+
+        assert!(recover_interrupted_checkpoint(root, &conn).is_err());
+        assert!(pending_path(root).exists());
+    }
+
+    #[test]
+    fn create_checkpoint_leaves_no_marker_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        conn.execute("INSERT INTO tracks(name, head) VALUES ('main', NULL)", [])
+            .unwrap();
+
+        let id = create_checkpoint(
+            root,
+            &conn,
+            "main",
+            None,
+            &manifest(&[]),
+            "note",
+            CheckpointKind::Manual,
+        )
+        .unwrap();
+        assert!(!pending_path(root).exists());
+        assert_eq!(read_head(root).unwrap().1, Some(id));
+    }
+}
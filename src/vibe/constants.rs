@@ -3,4 +3,8 @@ pub const DB_FILENAME: &str = "meta.db";
 pub const OBJECTS_DIRNAME: &str = "objects";
 pub const SNAPSHOTS_DIRNAME: &str = "snapshots";
 pub const HEAD_FILENAME: &str = "HEAD";
+pub const INDEX_FILENAME: &str = "index.json";
+pub const QUARANTINE_DIRNAME: &str = "quarantine";
+pub const PENDING_CHECKPOINT_FILENAME: &str = "PENDING";
+pub const LOCK_FILENAME: &str = "LOCK";
 pub const DEFAULT_TRACK: &str = "main";
@@ -1,17 +1,32 @@
 use crate::error::{AppError, Result};
-use rusqlite::Connection;
+use colored::*;
+use rusqlite::{Connection, TransactionBehavior};
 use std::path::Path;
+use std::time::Duration;
 
+use super::checkpoint::record_checkpoint_files;
 use super::constants::{DB_FILENAME, REPO_DIRNAME};
+use super::snapshot::load_snapshot_manifest;
 
-pub fn db_connect(root: &Path) -> Result<Connection> {
-    let db_path = root.join(REPO_DIRNAME).join(DB_FILENAME);
-    Connection::open(db_path).map_err(AppError::DbError)
+/// One step of the schema history
+struct Migration {
+    /// Tells the user about anything `sql` is going to drop, run just before it
+    report: Option<fn(&Connection) -> Result<()>>,
+    sql: &'static str,
+    /// Rows that can only be computed outside SQL (e.g. from manifests), filled
+    /// in after `sql` within the same transaction
+    backfill: Option<fn(&Connection, &Path) -> Result<()>>,
 }
 
-pub fn ensure_schema(conn: &Connection) -> Result<()> {
-    conn.execute_batch(
-        "
+/// Schema changes in the order they were introduced. `PRAGMA user_version`
+/// records how many of them a database has had applied, so add new ones at
+/// the end and never edit one that has shipped.
+const MIGRATIONS: &[Migration] = &[
+    // 1: the original tables. Repos from before versioning already have some
+    // or all of them at user_version 0, hence IF NOT EXISTS.
+    Migration {
+        report: None,
+        sql: "
         CREATE TABLE IF NOT EXISTS tracks (
             name TEXT PRIMARY KEY,
             head TEXT
@@ -25,7 +40,400 @@ pub fn ensure_schema(conn: &Connection) -> Result<()> {
             note TEXT,
             is_auto INTEGER DEFAULT 0
         );
+
+        CREATE TABLE IF NOT EXISTS operations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            op TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            argv TEXT,
+            head_before TEXT NOT NULL,
+            head_after TEXT NOT NULL,
+            tracks_before TEXT NOT NULL,
+            tracks_after TEXT NOT NULL,
+            touches_workspace INTEGER DEFAULT 0,
+            undone INTEGER DEFAULT 0
+        );
         ",
-    )
-    .map_err(AppError::DbError)
+        backfill: None,
+    },
+    // 2: foreign keys from parents and track heads to checkpoints, indexes
+    // for the common lookups, and the path -> checkpoint table. SQLite cannot
+    // add constraints to existing tables, so both are rebuilt; references
+    // that already point nowhere cannot be carried over, so they are listed
+    // and then cleared.
+    Migration {
+        report: Some(report_dangling_references),
+        sql: "
+        CREATE TABLE checkpoints_new (
+            id TEXT PRIMARY KEY,
+            track TEXT,
+            parent TEXT REFERENCES checkpoints(id) DEFERRABLE INITIALLY DEFERRED,
+            timestamp INTEGER,
+            note TEXT,
+            is_auto INTEGER DEFAULT 0
+        );
+        INSERT INTO checkpoints_new(id, track, parent, timestamp, note, is_auto)
+            SELECT id, track,
+                   CASE WHEN parent IN (SELECT id FROM checkpoints) THEN parent END,
+                   timestamp, note, is_auto
+            FROM checkpoints;
+        DROP TABLE checkpoints;
+        ALTER TABLE checkpoints_new RENAME TO checkpoints;
+
+        CREATE TABLE tracks_new (
+            name TEXT PRIMARY KEY,
+            head TEXT REFERENCES checkpoints(id) DEFERRABLE INITIALLY DEFERRED
+        );
+        INSERT INTO tracks_new(name, head)
+            SELECT name, CASE WHEN head IN (SELECT id FROM checkpoints) THEN head END
+            FROM tracks;
+        DROP TABLE tracks;
+        ALTER TABLE tracks_new RENAME TO tracks;
+
+        CREATE INDEX idx_checkpoints_track_timestamp ON checkpoints(track, timestamp);
+        CREATE INDEX idx_checkpoints_parent ON checkpoints(parent);
+        CREATE INDEX idx_tracks_head ON tracks(head);
+
+        CREATE TABLE checkpoint_files (
+            checkpoint_id TEXT NOT NULL REFERENCES checkpoints(id) ON DELETE CASCADE,
+            path TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            PRIMARY KEY (checkpoint_id, path)
+        ) WITHOUT ROWID;
+        CREATE INDEX idx_checkpoint_files_path ON checkpoint_files(path);
+        ",
+        backfill: Some(backfill_checkpoint_files),
+    },
+    // 3: why each checkpoint was made. Rescue checkpoints used to be stored
+    // as auto-snaps, so retention could prune them; they are recognised by
+    // the note restore gave them and taken out of is_auto.
+    Migration {
+        report: None,
+        sql: "
+        ALTER TABLE checkpoints ADD COLUMN kind TEXT NOT NULL DEFAULT 'manual';
+        UPDATE checkpoints SET kind = CASE
+            WHEN is_auto = 1 AND note LIKE 'Rescue snapshot before restoring %' THEN 'rescue'
+            WHEN is_auto = 1 THEN 'auto'
+            ELSE 'manual'
+        END;
+        UPDATE checkpoints SET is_auto = 0 WHERE kind = 'rescue';
+        CREATE INDEX idx_checkpoints_kind ON checkpoints(kind);
+        ",
+        backfill: None,
+    },
+];
+
+/// The schema version this build of vibesnap reads and writes
+const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// How long to wait for another process that is writing to the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn db_connect(root: &Path) -> Result<Connection> {
+    let db_path = root.join(REPO_DIRNAME).join(DB_FILENAME);
+    let mut conn = Connection::open(db_path).map_err(AppError::DbError)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    // Repos created by older versions are brought up to date before any
+    // command touches their tables. Rebuilding a table in a migration needs
+    // foreign key checks off, and the pragma is a no-op inside a transaction,
+    // so it is toggled around the whole run.
+    conn.pragma_update(None, "foreign_keys", false)?;
+    migrate_schema(&mut conn, root)?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(conn)
+}
+
+fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(AppError::DbError)
+}
+
+/// Apply every migration the database has not had yet, each in its own
+/// transaction together with the version bump
+fn migrate_schema(conn: &mut Connection, root: &Path) -> Result<()> {
+    let found = schema_version(conn)?;
+    if found > SCHEMA_VERSION {
+        return Err(AppError::SchemaTooNew(found, SCHEMA_VERSION));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(found as usize) {
+        let version = index as u32 + 1;
+        // Taking the write lock up front means a second process migrating
+        // at the same time waits here, then sees the work already done
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if schema_version(&tx)? >= version {
+            continue;
+        }
+        if let Some(report) = migration.report {
+            report(&tx)?;
+        }
+        tx.execute_batch(migration.sql)?;
+        if let Some(backfill) = migration.backfill {
+            backfill(&tx, root)?;
+        }
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Warn about each parent link and track head naming a checkpoint that does
+/// not exist, which the foreign key migration clears
+fn report_dangling_references(conn: &Connection) -> Result<()> {
+    let dangling = |sql: &str| -> Result<Vec<(String, String)>> {
+        Ok(conn
+            .prepare(sql)?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<_, _>>()?)
+    };
+    let warn = |message: String| {
+        eprintln!(
+            "{}",
+            format!("Warning: upgrading the database: {}", message).yellow()
+        )
+    };
+
+    for (id, parent) in dangling(
+        "SELECT id, parent FROM checkpoints
+         WHERE parent NOT IN (SELECT id FROM checkpoints) ORDER BY id",
+    )? {
+        warn(format!(
+            "checkpoint {} had unknown parent {}; it is now a root checkpoint",
+            id, parent
+        ));
+    }
+    for (track, head) in dangling(
+        "SELECT name, head FROM tracks
+         WHERE head NOT IN (SELECT id FROM checkpoints) ORDER BY name",
+    )? {
+        warn(format!(
+            "track {} pointed to unknown checkpoint {}; it now has no head",
+            track, head
+        ));
+    }
+    Ok(())
+}
+
+/// Index the files of every existing checkpoint. A checkpoint whose manifest
+/// is missing or unreadable is left out; `fsck` reports those.
+fn backfill_checkpoint_files(conn: &Connection, root: &Path) -> Result<()> {
+    let ids: Vec<String> = conn
+        .prepare("SELECT id FROM checkpoints")?
+        .query_map([], |row| row.get(0))?
+        .collect::<std::result::Result<_, _>>()?;
+    for id in ids {
+        if let Ok(manifest) = load_snapshot_manifest(root, &id) {
+            record_checkpoint_files(conn, &id, &manifest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::entry::FileEntry;
+    use crate::vibe::snapshot::{SnapshotManifest, save_snapshot_manifest};
+    use std::fs;
+
+    /// A repo whose database has the original tables, as created by versions
+    /// of vibesnap before the schema was versioned
+    fn legacy_repo() -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        let conn = Connection::open(dir.path().join(REPO_DIRNAME).join(DB_FILENAME)).unwrap();
+        conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+        (dir, conn)
+    }
+
+    #[test]
+    fn new_database_gets_current_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(dir.path()).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        drop(conn);
+        // Opening again has nothing left to do
+        let conn = db_connect(dir.path()).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn unversioned_database_is_migrated_in_place() {
+        let (dir, legacy) = legacy_repo();
+        legacy
+            .execute_batch(
+                "INSERT INTO checkpoints(id, track, timestamp, note) VALUES ('A', 'main', 1, 'first');
+                 INSERT INTO tracks(name, head) VALUES ('main', 'A');",
+            )
+            .unwrap();
+        drop(legacy);
+
+        let conn = db_connect(dir.path()).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let note: String = conn
+            .query_row("SELECT note FROM checkpoints WHERE id = 'A'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(note, "first");
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let (dir, legacy) = legacy_repo();
+        legacy
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        drop(legacy);
+        assert!(matches!(
+            db_connect(dir.path()),
+            Err(AppError::SchemaTooNew(found, supported))
+                if found == SCHEMA_VERSION + 1 && supported == SCHEMA_VERSION
+        ));
+    }
+
+    #[test]
+    fn foreign_key_migration_clears_dangling_references() {
+        let (dir, legacy) = legacy_repo();
+        legacy
+            .execute_batch(
+                "INSERT INTO checkpoints(id, track, parent) VALUES ('A', 'main', NULL);
+                 INSERT INTO checkpoints(id, track, parent) VALUES ('B', 'main', 'A');
+                 INSERT INTO checkpoints(id, track, parent) VALUES ('C', 'main', 'PRUNED');
+                 INSERT INTO tracks(name, head) VALUES ('main', 'B'), ('stale', 'PRUNED');
+                 PRAGMA user_version = 1;",
+            )
+            .unwrap();
+        drop(legacy);
+
+        let conn = db_connect(dir.path()).unwrap();
+        let parents: Vec<(String, Option<String>)> = conn
+            .prepare("SELECT id, parent FROM checkpoints ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            parents,
+            vec![
+                ("A".to_string(), None),
+                ("B".to_string(), Some("A".to_string())),
+                ("C".to_string(), None),
+            ]
+        );
+        let heads: Vec<(String, Option<String>)> = conn
+            .prepare("SELECT name, head FROM tracks ORDER BY name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            heads,
+            vec![
+                ("main".to_string(), Some("B".to_string())),
+                ("stale".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn foreign_key_migration_backfills_checkpoint_files() {
+        let (dir, legacy) = legacy_repo();
+        legacy
+            .execute_batch(
+                "INSERT INTO checkpoints(id, track) VALUES ('A', 'main'), ('NO-MANIFEST', 'main');
+                 PRAGMA user_version = 1;",
+            )
+            .unwrap();
+        drop(legacy);
+        let mut manifest = SnapshotManifest::default();
+        for (path, hash) in [("src/main.rs", "h1"), ("README.md", "h2")] {
+            manifest
+                .files
+                .insert(path.to_string(), FileEntry::regular(hash.to_string()));
+        }
+        save_snapshot_manifest(dir.path(), "A", &manifest).unwrap();
+
+        let conn = db_connect(dir.path()).unwrap();
+        let files: Vec<(String, String, String)> = conn
+            .prepare("SELECT checkpoint_id, path, hash FROM checkpoint_files ORDER BY path")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                ("A".into(), "README.md".into(), "h2".into()),
+                ("A".into(), "src/main.rs".into(), "h1".into()),
+            ]
+        );
+
+        // Deleting the checkpoint takes its file rows with it
+        conn.execute("DELETE FROM checkpoints WHERE id = 'A'", [])
+            .unwrap();
+        let remaining: i64 = conn
+            .query_row("SELECT COUNT(*) FROM checkpoint_files", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn foreign_keys_are_enforced_after_connecting() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(dir.path()).unwrap();
+        assert!(
+            conn.execute(
+                "INSERT INTO checkpoints(id, track, parent) VALUES ('A', 'main', 'MISSING')",
+                [],
+            )
+            .is_err()
+        );
+        assert!(
+            conn.execute(
+                "INSERT INTO tracks(name, head) VALUES ('main', 'MISSING')",
+                []
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn kind_migration_takes_rescues_out_of_auto_snaps() {
+        let (dir, legacy) = legacy_repo();
+        legacy
+            .execute_batch(
+                "INSERT INTO checkpoints(id, track, note, is_auto) VALUES
+                     ('M', 'main', 'manual', 0),
+                     ('A', 'main', 'Auto-snapshot', 1),
+                     ('R', 'main', 'Rescue snapshot before restoring abc', 1);",
+            )
+            .unwrap();
+        drop(legacy);
+
+        let conn = db_connect(dir.path()).unwrap();
+        let kinds: Vec<(String, String, bool)> = conn
+            .prepare("SELECT id, kind, is_auto FROM checkpoints ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            kinds,
+            vec![
+                ("A".into(), "auto".into(), true),
+                ("M".into(), "manual".into(), false),
+                ("R".into(), "rescue".into(), false),
+            ]
+        );
+    }
 }
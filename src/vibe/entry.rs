@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What kind of filesystem entry a manifest path records
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    #[default]
+    Regular,
+    Executable,
+    Symlink,
+}
+
+/// One path in a snapshot manifest. For symlinks the stored object holds the
+/// link target rather than the content of whatever it points at.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "ManifestValue")]
+pub struct FileEntry {
+    pub hash: String,
+    pub kind: FileKind,
+    /// Unix permission bits; `None` for symlinks, entries from manifests
+    /// written before modes were recorded, and platforms without them.
+    /// Written in octal ("755") like `chmod` takes it.
+    #[serde(skip_serializing_if = "Option::is_none", with = "octal_mode")]
+    pub mode: Option<u32>,
+}
+
+/// Manifests written before entries carried a kind map paths to bare hashes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestValue {
+    Hash(String),
+    Entry {
+        hash: String,
+        #[serde(default)]
+        kind: FileKind,
+        #[serde(default, with = "octal_mode")]
+        mode: Option<u32>,
+    },
+}
+
+impl From<ManifestValue> for FileEntry {
+    fn from(value: ManifestValue) -> Self {
+        match value {
+            ManifestValue::Hash(hash) => FileEntry::regular(hash),
+            ManifestValue::Entry { hash, kind, mode } => FileEntry { hash, kind, mode },
+        }
+    }
+}
+
+impl FileEntry {
+    /// A regular file with no recorded mode, as old manifests describe it
+    pub fn regular(hash: String) -> Self {
+        FileEntry {
+            hash,
+            kind: FileKind::Regular,
+            mode: None,
+        }
+    }
+
+    /// Describe a file from its `symlink_metadata`, falling back to a plain
+    /// regular file when the metadata could not be read
+    pub fn from_metadata(hash: String, metadata: Option<&fs::Metadata>) -> Self {
+        let Some(metadata) = metadata else {
+            return FileEntry::regular(hash);
+        };
+        if metadata.file_type().is_symlink() {
+            return FileEntry {
+                hash,
+                kind: FileKind::Symlink,
+                mode: None,
+            };
+        }
+        let mode = permission_bits(metadata);
+        let kind = match mode {
+            Some(bits) if bits & 0o111 != 0 => FileKind::Executable,
+            _ => FileKind::Regular,
+        };
+        FileEntry { hash, kind, mode }
+    }
+
+    /// Whether the working tree needs rewriting to turn `other` into this
+    /// entry. Modes are only compared when both sides recorded one, so
+    /// entries from old manifests are not reported as changed.
+    pub fn differs_from(&self, other: &FileEntry) -> bool {
+        if self.hash != other.hash
+            || (self.kind == FileKind::Symlink) != (other.kind == FileKind::Symlink)
+        {
+            return true;
+        }
+        matches!((self.mode, other.mode), (Some(a), Some(b)) if a != b)
+    }
+}
+
+mod octal_mode {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+        match mode {
+            Some(mode) => serializer.serialize_str(&format!("{:o}", mode)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u32>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|mode| u32::from_str_radix(&mode, 8).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// The bytes to store for a path: a symlink's target, or a file's content
+pub fn read_entry_content(path: &Path, metadata: Option<&fs::Metadata>) -> io::Result<Vec<u8>> {
+    if metadata.is_some_and(|m| m.file_type().is_symlink()) {
+        Ok(link_target_bytes(&fs::read_link(path)?))
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Recreate `entry` at `rel_path` under `root` from its stored content.
+/// Nothing in the way is followed: symlinks and files where a directory is
+/// needed, and a symlink or empty directory at the path itself, are replaced.
+pub fn write_entry(
+    root: &Path,
+    rel_path: &str,
+    entry: &FileEntry,
+    content: Vec<u8>,
+) -> io::Result<()> {
+    if let Some(parent) = Path::new(rel_path).parent() {
+        create_dir_in_tree(root, parent)?;
+    }
+    let dest = root.join(rel_path);
+    match fs::symlink_metadata(&dest) {
+        // A directory still holding anything fails here, leaving it intact
+        Ok(m) if m.is_dir() => fs::remove_dir(&dest)?,
+        Ok(m) if m.file_type().is_symlink() || entry.kind == FileKind::Symlink => {
+            fs::remove_file(&dest)?
+        }
+        _ => {}
+    }
+
+    if entry.kind == FileKind::Symlink {
+        return create_symlink(&content, &dest);
+    }
+    fs::write(&dest, content)?;
+    if let Some(mode) = entry.mode {
+        set_permission_bits(&dest, mode)?;
+    }
+    Ok(())
+}
+
+/// Make `rel_dir` under `root` a real directory, creating whatever is
+/// missing. A file or symlink where a directory belongs is removed rather
+/// than followed, so nothing is ever created outside `root`.
+pub fn create_dir_in_tree(root: &Path, rel_dir: &Path) -> io::Result<()> {
+    let mut current = root.to_path_buf();
+    for component in rel_dir.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(m) if m.is_dir() => continue,
+            Ok(_) => fs::remove_file(&current)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        fs::create_dir(&current)?;
+    }
+    Ok(())
+}
+
+/// Read, write and execute bits for owner, group and others. setuid, setgid
+/// and sticky are never recorded or restored.
+const PERMISSION_MASK: u32 = 0o777;
+
+#[cfg(unix)]
+fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & PERMISSION_MASK)
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_permission_bits(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // Manifests written before the mask may still carry the special bits
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & PERMISSION_MASK))
+}
+
+#[cfg(not(unix))]
+fn set_permission_bits(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn link_target_bytes(target: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    target.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn link_target_bytes(target: &Path) -> Vec<u8> {
+    target.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &[u8], dest: &Path) -> io::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    std::os::unix::fs::symlink(OsStr::from_bytes(target), dest)
+}
+
+/// Without unix symlinks the link is checked out as a small file holding its
+/// target, the same fallback git uses
+#[cfg(not(unix))]
+fn create_symlink(target: &[u8], dest: &Path) -> io::Result<()> {
+    fs::write(dest, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_hashes_from_old_manifests_read_as_regular_files() {
+        let entry: FileEntry = serde_json::from_str(r#""abc""#).unwrap();
+        assert_eq!(entry, FileEntry::regular("abc".to_string()));
+
+        let entry: FileEntry =
+            serde_json::from_str(r#"{"hash":"abc","kind":"executable","mode":"755"}"#).unwrap();
+        assert_eq!(entry.kind, FileKind::Executable);
+        assert_eq!(entry.mode, Some(0o755));
+    }
+
+    #[test]
+    fn modes_are_written_in_octal_and_omitted_when_unknown() {
+        let entry = FileEntry {
+            hash: "abc".to_string(),
+            kind: FileKind::Regular,
+            mode: Some(0o644),
+        };
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"hash":"abc","kind":"regular","mode":"644"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&FileEntry::regular("abc".to_string())).unwrap(),
+            r#"{"hash":"abc","kind":"regular"}"#
+        );
+    }
+
+    #[test]
+    fn missing_modes_are_not_a_difference() {
+        let old = FileEntry::regular("abc".to_string());
+        let new = FileEntry {
+            mode: Some(0o755),
+            kind: FileKind::Executable,
+            ..old.clone()
+        };
+        assert!(!new.differs_from(&old));
+        assert!(new.differs_from(&FileEntry {
+            mode: Some(0o644),
+            ..new.clone()
+        }));
+        assert!(new.differs_from(&FileEntry::regular("def".to_string())));
+        assert!(new.differs_from(&FileEntry {
+            kind: FileKind::Symlink,
+            mode: None,
+            ..new.clone()
+        }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executables_and_symlinks_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("run.sh");
+        let script_entry = FileEntry {
+            hash: "h".to_string(),
+            kind: FileKind::Executable,
+            mode: Some(0o750),
+        };
+        write_entry(dir.path(), "run.sh", &script_entry, b"#!/bin/sh\n".to_vec()).unwrap();
+        let metadata = fs::symlink_metadata(&script).unwrap();
+        assert_eq!(
+            FileEntry::from_metadata("h".to_string(), Some(&metadata)),
+            script_entry
+        );
+
+        let link = dir.path().join("link");
+        let link_entry = FileEntry {
+            hash: "h".to_string(),
+            kind: FileKind::Symlink,
+            mode: None,
+        };
+        write_entry(dir.path(), "link", &link_entry, b"run.sh".to_vec()).unwrap();
+        let metadata = fs::symlink_metadata(&link).unwrap();
+        assert_eq!(
+            FileEntry::from_metadata("h".to_string(), Some(&metadata)),
+            link_entry
+        );
+        assert_eq!(
+            read_entry_content(&link, Some(&metadata)).unwrap(),
+            b"run.sh"
+        );
+
+        // Writing a regular file over the link replaces the link instead of
+        // following it
+        write_entry(
+            dir.path(),
+            "link",
+            &FileEntry::regular("h".to_string()),
+            b"plain".to_vec(),
+        )
+        .unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_file());
+        assert_eq!(fs::read(&script).unwrap(), b"#!/bin/sh\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_parents_are_replaced_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::os::unix::fs::symlink(outside.path(), root.join("sub")).unwrap();
+
+        write_entry(
+            root,
+            "sub/file.txt",
+            &FileEntry::regular("h".to_string()),
+            b"x".to_vec(),
+        )
+        .unwrap();
+
+        assert!(fs::symlink_metadata(root.join("sub")).unwrap().is_dir());
+        assert_eq!(fs::read(root.join("sub/file.txt")).unwrap(), b"x");
+        assert!(fs::read_dir(outside.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn files_and_directories_swap_places() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        // A file where a directory is now needed
+        fs::write(root.join("a"), "old file").unwrap();
+        write_entry(
+            root,
+            "a/b.txt",
+            &FileEntry::regular("h".to_string()),
+            b"new".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(fs::read(root.join("a/b.txt")).unwrap(), b"new");
+
+        // An empty directory where a file is now needed
+        fs::create_dir(root.join("c")).unwrap();
+        write_entry(
+            root,
+            "c",
+            &FileEntry::regular("h".to_string()),
+            b"file".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(fs::read(root.join("c")).unwrap(), b"file");
+
+        // A directory that still holds files is left alone
+        assert!(
+            write_entry(
+                root,
+                "a",
+                &FileEntry::regular("h".to_string()),
+                b"x".to_vec()
+            )
+            .is_err()
+        );
+        assert_eq!(fs::read(root.join("a/b.txt")).unwrap(), b"new");
+
+        create_dir_in_tree(root, Path::new("c/d")).unwrap();
+        assert!(root.join("c/d").is_dir());
+    }
+
+    #[test]
+    fn special_permission_bits_are_never_recorded_or_restored() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tool");
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o1755)).unwrap();
+        assert_eq!(permission_bits(&fs::metadata(&path).unwrap()), Some(0o755));
+
+        set_permission_bits(&path, 0o4750).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o7777,
+            0o750
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use crate::error::{AppError, Result};
+use rusqlite::Connection;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::constants::{HEAD_FILENAME, QUARANTINE_DIRNAME, REPO_DIRNAME, SNAPSHOTS_DIRNAME};
+use super::objects::{decode_object, hash_file_content, list_objects};
+use super::repo::read_head;
+use super::snapshot::{load_snapshot_manifest, load_snapshot_tree};
+use super::tree::load_tree_partial;
+use super::utils::{parallel_map, resolve_jobs};
+
+/// One integrity problem found by `check_repository`
+#[derive(Debug, Clone)]
+pub enum Problem {
+    /// The object cannot be decoded or does not hash to its name
+    CorruptObject {
+        hash: String,
+        path: PathBuf,
+        reason: String,
+    },
+    MissingManifest {
+        checkpoint: String,
+    },
+    /// The manifest exists but cannot be parsed
+    BadManifest {
+        checkpoint: String,
+        path: PathBuf,
+        reason: String,
+    },
+    /// A manifest entry whose object is missing or corrupt. For a directory
+    /// (`file` ending in `/`) the tree object is the broken one, and nothing
+    /// below it could be checked.
+    BrokenEntry {
+        checkpoint: String,
+        file: String,
+        hash: String,
+    },
+    DanglingParent {
+        checkpoint: String,
+        parent: String,
+    },
+    DanglingTrackHead {
+        track: String,
+        head: String,
+    },
+    BadHead(String),
+    /// A manifest with no checkpoint row, e.g. from an interrupted snap
+    OrphanManifest {
+        checkpoint: String,
+    },
+}
+
+impl Problem {
+    /// Orphaned manifests are harmless leftovers that `gc` cleans up
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Problem::OrphanManifest { .. })
+    }
+
+    /// The file to move aside when repairing, if this problem has one
+    fn quarantine_path(&self) -> Option<&Path> {
+        match self {
+            Problem::CorruptObject { path, .. } | Problem::BadManifest { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::CorruptObject { hash, reason, .. } => {
+                write!(f, "corrupt object {}: {}", hash, reason)
+            }
+            Problem::MissingManifest { checkpoint } => {
+                write!(f, "checkpoint {} has no manifest", checkpoint)
+            }
+            Problem::BadManifest {
+                checkpoint, reason, ..
+            } => write!(f, "unreadable manifest for {}: {}", checkpoint, reason),
+            Problem::BrokenEntry {
+                checkpoint,
+                file,
+                hash,
+            } => write!(
+                f,
+                "checkpoint {} {} {} refers to missing or corrupt object {}",
+                checkpoint,
+                if file.ends_with('/') {
+                    "directory"
+                } else {
+                    "file"
+                },
+                file,
+                hash
+            ),
+            Problem::DanglingParent { checkpoint, parent } => {
+                write!(f, "checkpoint {} has unknown parent {}", checkpoint, parent)
+            }
+            Problem::DanglingTrackHead { track, head } => {
+                write!(f, "track {} points to unknown checkpoint {}", track, head)
+            }
+            Problem::BadHead(reason) => write!(f, "HEAD: {}", reason),
+            Problem::OrphanManifest { checkpoint } => write!(
+                f,
+                "manifest {} has no checkpoint (run 'vibesnap gc' to remove it)",
+                checkpoint
+            ),
+        }
+    }
+}
+
+/// Verify every object against its hash, every checkpoint's manifest and its
+/// entries, the parent links, the track heads and HEAD
+pub fn check_repository(root: &Path, conn: &Connection, jobs: usize) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    // Objects: re-hash each one against its name
+    let objects = list_objects(root)?;
+    let verdicts = parallel_map(&objects, resolve_jobs(jobs), |(hash, path)| {
+        verify_object(hash, path)
+    });
+    let mut good_objects: HashSet<&str> = HashSet::new();
+    for ((hash, path), verdict) in objects.iter().zip(verdicts) {
+        match verdict {
+            Ok(()) => {
+                good_objects.insert(hash);
+            }
+            Err(reason) => problems.push(Problem::CorruptObject {
+                hash: hash.clone(),
+                path: path.clone(),
+                reason,
+            }),
+        }
+    }
+
+    // Checkpoints and their manifests
+    let checkpoints: BTreeMap<String, Option<String>> = conn
+        .prepare("SELECT id, parent FROM checkpoints")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    let snapshots_dir = root.join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME);
+
+    for (id, parent) in &checkpoints {
+        // Only a manifest file that fails to parse is reported (and
+        // quarantined) as bad; a damaged tree object it points to is broken
+        // for every checkpoint sharing it, and is reported on its hash
+        let loaded = load_snapshot_tree(root, id).and_then(|tree| match tree {
+            Some(tree) => Ok(load_tree_partial(root, &tree)),
+            None => load_snapshot_manifest(root, id).map(|manifest| (manifest, Vec::new())),
+        });
+        match loaded {
+            Ok((manifest, unreadable_trees)) => {
+                for (dir, hash) in unreadable_trees {
+                    problems.push(Problem::BrokenEntry {
+                        checkpoint: id.clone(),
+                        file: format!("{}/", dir),
+                        hash,
+                    });
+                }
+                for (file, entry) in manifest.files {
+                    if !good_objects.contains(entry.hash.as_str()) {
+                        problems.push(Problem::BrokenEntry {
+                            checkpoint: id.clone(),
+                            file,
+                            hash: entry.hash,
+                        });
+                    }
+                }
+            }
+            Err(AppError::ManifestNotFound(_)) => problems.push(Problem::MissingManifest {
+                checkpoint: id.clone(),
+            }),
+            Err(e) => problems.push(Problem::BadManifest {
+                checkpoint: id.clone(),
+                path: snapshots_dir.join(format!("{}.json", id)),
+                reason: e.to_string(),
+            }),
+        }
+
+        if let Some(parent) = parent
+            && !checkpoints.contains_key(parent)
+        {
+            problems.push(Problem::DanglingParent {
+                checkpoint: id.clone(),
+                parent: parent.clone(),
+            });
+        }
+    }
+
+    if snapshots_dir.is_dir() {
+        let mut orphans: Vec<String> = fs::read_dir(&snapshots_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+            .filter(|id| !checkpoints.contains_key(id))
+            .collect();
+        orphans.sort();
+        problems.extend(
+            orphans
+                .into_iter()
+                .map(|checkpoint| Problem::OrphanManifest { checkpoint }),
+        );
+    }
+
+    // Tracks and HEAD
+    let tracks: HashMap<String, Option<String>> = conn
+        .prepare("SELECT name, head FROM tracks")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    let mut track_names: Vec<&String> = tracks.keys().collect();
+    track_names.sort();
+    for track in track_names {
+        if let Some(head) = &tracks[track]
+            && !checkpoints.contains_key(head)
+        {
+            problems.push(Problem::DanglingTrackHead {
+                track: track.clone(),
+                head: head.clone(),
+            });
+        }
+    }
+
+    if !root.join(REPO_DIRNAME).join(HEAD_FILENAME).is_file() {
+        problems.push(Problem::BadHead("file is missing".to_string()));
+    } else {
+        match read_head(root) {
+            Ok((track, checkpoint)) => {
+                if !tracks.contains_key(&track) {
+                    problems.push(Problem::BadHead(format!("unknown track {}", track)));
+                }
+                if let Some(checkpoint) = checkpoint
+                    && !checkpoints.contains_key(&checkpoint)
+                {
+                    problems.push(Problem::BadHead(format!(
+                        "unknown checkpoint {}",
+                        checkpoint
+                    )));
+                }
+            }
+            Err(e) => problems.push(Problem::BadHead(e.to_string())),
+        }
+    }
+
+    Ok(problems)
+}
+
+fn verify_object(hash: &str, path: &Path) -> std::result::Result<(), String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let content = decode_object(hash, &data).map_err(|e| match e {
+        AppError::CorruptObject(_, reason) => reason,
+        e => e.to_string(),
+    })?;
+    if hash_file_content(&content) != hash {
+        return Err("content does not match its hash".to_string());
+    }
+    Ok(())
+}
+
+/// Move corrupt objects and unreadable manifests into `.vibe/quarantine`, so
+/// they stop being served but can still be inspected. Returns the new
+/// locations.
+pub fn quarantine(root: &Path, problems: &[Problem]) -> Result<Vec<PathBuf>> {
+    let vibe_dir = root.join(REPO_DIRNAME);
+    let quarantine_dir = vibe_dir.join(QUARANTINE_DIRNAME);
+    let mut moved = Vec::new();
+    for path in problems.iter().filter_map(Problem::quarantine_path) {
+        let Ok(relative) = path.strip_prefix(&vibe_dir) else {
+            continue;
+        };
+        let target = quarantine_dir.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(path, &target)?;
+        moved.push(target);
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::db::db_connect;
+    use crate::vibe::entry::FileEntry;
+    use crate::vibe::objects::{object_path, store_content_in_objects};
+    use crate::vibe::repo::write_head;
+    use crate::vibe::snapshot::{SnapshotManifest, save_snapshot_manifest};
+
+    /// A repo on track `main` with checkpoints A <- B, HEAD at B
+    fn healthy_repo() -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        for (id, parent, content) in [("A", None, "one"), ("B", Some("A"), "two")] {
+            let hash = store_content_in_objects(root, content.as_bytes()).unwrap();
+            let manifest = SnapshotManifest {
+                files: [("f.txt".to_string(), FileEntry::regular(hash))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            };
+            save_snapshot_manifest(root, id, &manifest).unwrap();
+            conn.execute(
+                "INSERT INTO checkpoints(id, track, parent) VALUES (?, 'main', ?)",
+                rusqlite::params![id, parent],
+            )
+            .unwrap();
+        }
+        conn.execute("INSERT INTO tracks(name, head) VALUES ('main', 'B')", [])
+            .unwrap();
+        write_head(root, "main", Some("B")).unwrap();
+        (dir, conn)
+    }
+
+    #[test]
+    fn healthy_repo_has_no_problems() {
+        let (dir, conn) = healthy_repo();
+        assert!(check_repository(dir.path(), &conn, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn corrupt_objects_are_reported_and_quarantined() {
+        let (dir, conn) = healthy_repo();
+        let root = dir.path();
+        let hash = hash_file_content(b"one");
+        let path = object_path(root, &hash);
+        fs::write(&path, "bit rot").unwrap();
+
+        let problems = check_repository(root, &conn, 2).unwrap();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(matches!(&problems[0], Problem::CorruptObject { hash: h, .. } if *h == hash));
+        assert!(matches!(
+            &problems[1],
+            Problem::BrokenEntry { checkpoint, file, .. } if checkpoint == "A" && file == "f.txt"
+        ));
+        assert!(!problems.iter().any(Problem::is_warning));
+
+        let moved = quarantine(root, &problems).unwrap();
+        assert_eq!(moved.len(), 1);
+        assert!(!path.exists());
+        assert_eq!(fs::read(&moved[0]).unwrap(), b"bit rot");
+        assert!(moved[0].starts_with(root.join(REPO_DIRNAME).join(QUARANTINE_DIRNAME)));
+    }
+
+    #[test]
+    fn broken_links_and_manifests_are_reported() {
+        let (dir, conn) = healthy_repo();
+        let root = dir.path();
+        let snapshots_dir = root.join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME);
+        fs::write(snapshots_dir.join("B.json"), "{not json").unwrap();
+        fs::write(snapshots_dir.join("LEFTOVER.json"), r#"{"files":{}}"#).unwrap();
+        // Only a database damaged outside vibesnap can break the foreign keys
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        conn.execute("UPDATE checkpoints SET parent = 'GONE' WHERE id = 'A'", [])
+            .unwrap();
+        conn.execute("INSERT INTO tracks(name, head) VALUES ('old', 'NOPE')", [])
+            .unwrap();
+        write_head(root, "missing", Some("B")).unwrap();
+
+        let problems = check_repository(root, &conn, 1).unwrap();
+        let described: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        assert!(matches!(&problems[0], Problem::DanglingParent { parent, .. } if parent == "GONE"));
+        assert!(
+            matches!(&problems[1], Problem::BadManifest { checkpoint, .. } if checkpoint == "B")
+        );
+        assert!(problems[2].is_warning());
+        assert!(matches!(&problems[3], Problem::DanglingTrackHead { track, .. } if track == "old"));
+        assert_eq!(described[4], "HEAD: unknown track missing");
+        assert_eq!(problems.len(), 5, "{:?}", described);
+
+        quarantine(root, &problems).unwrap();
+        assert!(!snapshots_dir.join("B.json").exists());
+        assert!(snapshots_dir.join("LEFTOVER.json").exists());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::error::{AppError, Result};
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::constants::{REPO_DIRNAME, SNAPSHOTS_DIRNAME};
+use super::objects::list_objects;
+use super::snapshot::{load_snapshot_manifest, load_snapshot_tree};
+use super::tree::collect_tree_objects;
+use super::utils::TEMP_FILE_INFIX;
+
+const TEMP_FILE_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// A file in the store that nothing refers to any more
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    /// Object hash or checkpoint ID
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Everything `gc` would delete
+#[derive(Debug, Default)]
+pub struct Garbage {
+    pub objects: Vec<Orphan>,
+    pub manifests: Vec<Orphan>,
+    /// Leftovers of atomic writes that never got renamed into place
+    pub temp_files: Vec<Orphan>,
+}
+
+impl Garbage {
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.manifests.is_empty() && self.temp_files.is_empty()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.objects
+            .iter()
+            .chain(self.manifests.iter())
+            .chain(self.temp_files.iter())
+            .map(|orphan| orphan.size)
+            .sum()
+    }
+}
+
+/// Find objects not referenced by the manifest or trees of any checkpoint in
+/// the database, and manifests whose checkpoint no longer exists (e.g. left
+/// by an interrupted snap). Refuses while any checkpoint is missing its
+/// manifest: the objects it needs may still be in the store, and a restored
+/// manifest would be useless once they were collected.
+pub fn find_garbage(root: &Path, conn: &Connection) -> Result<Garbage> {
+    let mut stmt = conn.prepare("SELECT id FROM checkpoints")?;
+    let checkpoint_ids: HashSet<String> = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<std::result::Result<_, _>>()?;
+
+    let mut reachable: HashSet<String> = HashSet::new();
+    let mut visited_trees: HashSet<String> = HashSet::new();
+    let mut without_manifest: Vec<&str> = Vec::new();
+    for id in &checkpoint_ids {
+        let loaded = load_snapshot_tree(root, id).and_then(|tree| match tree {
+            Some(tree) => collect_tree_objects(root, &tree, &mut visited_trees, &mut reachable),
+            None => load_snapshot_manifest(root, id).map(|manifest| {
+                reachable.extend(manifest.files.into_values().map(|entry| entry.hash))
+            }),
+        });
+        match loaded {
+            Ok(()) => {}
+            Err(AppError::ManifestNotFound(_)) => without_manifest.push(id),
+            // Anything else means we cannot tell what is referenced, so
+            // refuse rather than delete objects that might still be needed
+            Err(e) => return Err(e),
+        }
+    }
+    if !without_manifest.is_empty() {
+        without_manifest.sort_unstable();
+        return Err(AppError::CheckpointsWithoutManifest(
+            without_manifest.join(", "),
+        ));
+    }
+
+    let mut garbage = Garbage::default();
+    for (hash, path) in list_objects(root)? {
+        if !reachable.contains(&hash) {
+            let size = fs::metadata(&path)?.len();
+            garbage.objects.push(Orphan {
+                name: hash,
+                path,
+                size,
+            });
+        }
+    }
+
+    let snapshots_dir = root.join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME);
+    if snapshots_dir.is_dir() {
+        for entry in fs::read_dir(&snapshots_dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let id = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if !checkpoint_ids.contains(&id) {
+                let size = fs::metadata(&path)?.len();
+                garbage.manifests.push(Orphan {
+                    name: id,
+                    path,
+                    size,
+                });
+            }
+        }
+        garbage.manifests.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    garbage.temp_files = find_stale_temp_files(root)?;
+
+    Ok(garbage)
+}
+
+/// Temporary files from atomic writes that are old enough that no running
+/// command can still be about to rename them
+fn find_stale_temp_files(root: &Path) -> Result<Vec<Orphan>> {
+    let vibe_dir = root.join(REPO_DIRNAME);
+    let cutoff = SystemTime::now() - TEMP_FILE_GRACE;
+    let mut temp_files = Vec::new();
+    for entry in walkdir::WalkDir::new(&vibe_dir)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+    {
+        let name = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file() || !name.contains(TEMP_FILE_INFIX) {
+            continue;
+        }
+        let metadata = entry.metadata().map_err(std::io::Error::from)?;
+        if metadata.modified()? < cutoff {
+            temp_files.push(Orphan {
+                name: name.to_string(),
+                path: entry.path().to_path_buf(),
+                size: metadata.len(),
+            });
+        }
+    }
+    Ok(temp_files)
+}
+
+/// Delete everything in `garbage`, then any fan-out directories it leaves empty
+pub fn remove_garbage(garbage: &Garbage) -> Result<()> {
+    for orphan in garbage
+        .objects
+        .iter()
+        .chain(garbage.manifests.iter())
+        .chain(garbage.temp_files.iter())
+    {
+        fs::remove_file(&orphan.path)?;
+    }
+    for orphan in &garbage.objects {
+        if let Some(fan_dir) = orphan.path.parent() {
+            // Fails harmlessly while the directory still has objects in it
+            let _ = fs::remove_dir(fan_dir);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::db::db_connect;
+    use crate::vibe::entry::FileEntry;
+    use crate::vibe::objects::{hash_file_content, object_exists, store_content_in_objects};
+    use crate::vibe::snapshot::{SnapshotManifest, save_snapshot_manifest};
+
+    fn checkpoint(root: &Path, conn: &Connection, id: &str, files: &[(&str, &str)]) {
+        let manifest = SnapshotManifest {
+            files: files
+                .iter()
+                .map(|(path, content)| {
+                    let hash = store_content_in_objects(root, content.as_bytes()).unwrap();
+                    (path.to_string(), FileEntry::regular(hash))
+                })
+                .collect(),
+            ..Default::default()
+        };
+        save_snapshot_manifest(root, id, &manifest).unwrap();
+        conn.execute(
+            "INSERT INTO checkpoints(id, track) VALUES (?, 'main')",
+            [id],
+        )
+        .unwrap();
+    }
+
+    fn hash_of(content: &str) -> String {
+        hash_file_content(content.as_bytes())
+    }
+
+    #[test]
+    fn only_unreachable_objects_and_orphaned_manifests_are_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        checkpoint(
+            root,
+            &conn,
+            "KEEP1",
+            &[("a.txt", "shared"), ("b.txt", "one")],
+        );
+        checkpoint(root, &conn, "KEEP2", &[("a.txt", "shared")]);
+        checkpoint(root, &conn, "DROPPED", &[("c.txt", "only in dropped")]);
+        conn.execute("DELETE FROM checkpoints WHERE id = 'DROPPED'", [])
+            .unwrap();
+        let loose = store_content_in_objects(root, b"never snapped").unwrap();
+
+        let garbage = find_garbage(root, &conn).unwrap();
+        let objects: HashSet<&str> = garbage.objects.iter().map(|o| o.name.as_str()).collect();
+        assert!(objects.contains(hash_of("only in dropped").as_str()));
+        assert!(objects.contains(loose.as_str()));
+        assert!(!objects.contains(hash_of("shared").as_str()));
+        assert!(!objects.contains(hash_of("one").as_str()));
+        let manifests: Vec<&str> = garbage.manifests.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(manifests, vec!["DROPPED"]);
+        assert!(garbage.total_size() > 0);
+
+        remove_garbage(&garbage).unwrap();
+        assert!(!object_exists(root, &loose));
+        assert!(object_exists(root, &hash_of("shared")));
+        assert!(object_exists(root, &hash_of("one")));
+        assert!(load_snapshot_manifest(root, "KEEP1").is_ok());
+        assert!(load_snapshot_manifest(root, "KEEP2").is_ok());
+        assert!(find_garbage(root, &conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unreadable_manifests_stop_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        checkpoint(root, &conn, "BROKEN", &[("a.txt", "a")]);
+        fs::write(
+            root.join(REPO_DIRNAME)
+                .join(SNAPSHOTS_DIRNAME)
+                .join("BROKEN.json"),
+            "{not json",
+        )
+        .unwrap();
+
+        assert!(find_garbage(root, &conn).is_err());
+    }
+
+    #[test]
+    fn checkpoints_without_a_manifest_stop_collection() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(root).unwrap();
+        checkpoint(root, &conn, "KEEP", &[("a.txt", "a")]);
+        for id in ["LOST2", "LOST1"] {
+            conn.execute(
+                "INSERT INTO checkpoints(id, track) VALUES (?, 'main')",
+                [id],
+            )
+            .unwrap();
+        }
+
+        assert!(matches!(
+            find_garbage(root, &conn),
+            Err(AppError::CheckpointsWithoutManifest(ids)) if ids == "LOST1, LOST2"
+        ));
+    }
+}
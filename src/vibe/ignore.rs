@@ -1,104 +1,637 @@
+use crate::config::Config;
 use crate::error::Result;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-/// Read and parse ignore patterns from .gitignore (preferred) or .vibeignore (fallback)
-pub fn read_ignore_patterns(root: &Path) -> Result<Vec<String>> {
-    // First check for .gitignore
-    let gitignore_path: PathBuf = root.join(".gitignore");
-    if gitignore_path.exists() {
-        let content: String = fs::read_to_string(gitignore_path)?;
-        let patterns: Vec<String> = content
-            .lines()
-            .map(|line: &str| line.trim())
-            .filter(|line: &&str| !line.is_empty() && !line.starts_with('#'))
-            .map(|line: &str| line.to_string())
-            .collect();
-        return Ok(patterns);
-    }
-
-    // Fall back to .vibeignore
-    let vibeignore_path = root.join(".vibeignore");
-    if !vibeignore_path.exists() {
-        return Ok(Vec::new());
-    }
-
-    let content: String = fs::read_to_string(vibeignore_path)?;
-    let patterns: Vec<String> = content
-        .lines()
-        .map(|line: &str| line.trim())
-        .filter(|line: &&str| !line.is_empty() && !line.starts_with('#'))
-        .map(|line: &str| line.to_string())
-        .collect();
-
-    Ok(patterns)
+use super::constants::REPO_DIRNAME;
+
+/// Where an ignore file sits in the precedence order; later layers win
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum IgnoreLayer {
+    Global,
+    GitExclude,
+    Gitignore,
+    Vibeignore,
 }
 
-/// Check if a path should be ignored based on .vibeignore patterns
-pub fn should_ignore_path(path: &Path, root: &Path, patterns: &[String]) -> bool {
-    let relative_path: &Path = match path.strip_prefix(root) {
-        Ok(rel) => rel,
-        Err(_) => return false, // If not under root, don't ignore
-    };
+/// A single parsed line of an ignore file
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    /// The line as written in the ignore file
+    pub text: String,
+    /// The ignore file this rule came from
+    pub source: PathBuf,
+    /// 1-based line number within `source`
+    pub line: usize,
+    /// Directory of the ignore file relative to the root ("" for the root);
+    /// the rule only applies below it
+    base: String,
+    layer: IgnoreLayer,
+    /// Glob with the `!` prefix, leading `/` and trailing `/` removed
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns containing a `/` match the full relative path; others match
+    /// the basename at any depth
+    anchored: bool,
+}
 
-    let path_str: std::borrow::Cow<'_, str> = relative_path.to_string_lossy();
+impl IgnoreRule {
+    /// Parse one line using gitignore rules. Returns `None` for blank lines
+    /// and comments.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let trimmed = trim_unescaped_trailing_spaces(line);
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return None;
+        }
 
-    // Always ignore .git directory
-    if path_str == ".git" || path_str.starts_with(".git/") {
-        return true;
+        let (negated, body) = match trimmed.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed),
+        };
+        let (dir_only, body) = match body.strip_suffix('/') {
+            Some(rest) if !rest.ends_with('\\') => (true, rest),
+            _ => (false, body),
+        };
+        let anchored = body.contains('/');
+        let body = body.strip_prefix('/').unwrap_or(body);
+        if body.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            text: trimmed.to_string(),
+            source: PathBuf::new(),
+            line: 0,
+            base: String::new(),
+            layer: IgnoreLayer::Gitignore,
+            glob: body.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Whether this rule's pattern matches `rel_path` (relative to the repo
+    /// root, `/`-separated). Negation is not applied here.
+    pub fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let rel_path = if self.base.is_empty() {
+            rel_path
+        } else {
+            match rel_path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(rest) => rest,
+                None => return false,
+            }
+        };
+        let target = if self.anchored {
+            rel_path
+        } else {
+            rel_path.rsplit('/').next().unwrap_or(rel_path)
+        };
+        let text: Vec<char> = target.chars().collect();
+        wildmatch(&self.glob, &text)
+    }
+}
+
+/// An ordered set of ignore rules; later rules override earlier ones
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreMatcher {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreMatcher {
+    /// Append the rules of an ignore file, if it exists, keeping the rule
+    /// list ordered by layer and then by directory depth
+    fn add_file(&mut self, path: &Path, base: &str, layer: IgnoreLayer) -> Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(path)?;
+        for (index, line) in content.lines().enumerate() {
+            if let Some(mut rule) = IgnoreRule::parse(line) {
+                rule.source = path.to_path_buf();
+                rule.line = index + 1;
+                rule.base = base.to_string();
+                rule.layer = layer;
+                self.rules.push(rule);
+            }
+        }
+        // Stable sort keeps line order within a file
+        self.rules.sort_by_key(|rule| {
+            let depth = match rule.base.as_str() {
+                "" => 0,
+                base => base.split('/').count(),
+            };
+            (rule.layer, depth)
+        });
+        Ok(())
+    }
+
+    /// The last rule matching the path itself, ignoring parent directories
+    fn last_match(&self, rel_path: &str, is_dir: bool) -> Option<&IgnoreRule> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(rel_path, is_dir))
     }
 
-    for pattern in patterns {
-        if matches_pattern(&path_str, pattern) {
-            return true;
+    /// The rule that decides whether a relative path is ignored: either a
+    /// rule excluding one of its parent directories, or the last rule
+    /// matching the path itself (which may be a negation). As in git, a file
+    /// inside an ignored directory cannot be re-included.
+    pub fn explain(&self, rel_path: &str, is_dir: bool) -> Option<&IgnoreRule> {
+        let mut prefix_end = 0;
+        while let Some(pos) = rel_path[prefix_end..].find('/') {
+            let dir = &rel_path[..prefix_end + pos];
+            if let Some(rule) = self.last_match(dir, true)
+                && !rule.is_negated()
+            {
+                return Some(rule);
+            }
+            prefix_end += pos + 1;
         }
+        self.last_match(rel_path, is_dir)
+    }
+
+    /// Whether a relative path is ignored
+    pub fn is_ignored(&self, rel_path: &str, is_dir: bool) -> bool {
+        self.explain(rel_path, is_dir)
+            .is_some_and(|rule| !rule.is_negated())
     }
+}
+
+/// Read ignore rules from every source, lowest precedence first: the
+/// user-global ignore file, .git/info/exclude, .gitignore files (deeper
+/// directories win) and finally .vibeignore files, so snaps can exclude
+/// things git still tracks
+pub fn read_ignore_rules(root: &Path) -> Result<IgnoreMatcher> {
+    let mut matcher = IgnoreMatcher::default();
+    matcher.add_file(&Config::global_ignore_path(), "", IgnoreLayer::Global)?;
+    matcher.add_file(
+        &root.join(".git").join("info").join("exclude"),
+        "",
+        IgnoreLayer::GitExclude,
+    )?;
+    add_directory_ignore_files(root, root, &mut matcher)?;
+    Ok(matcher)
+}
 
-    false
+/// Whether `path` is one of the files `read_ignore_rules` loads, so a change
+/// to it means the rules need reading again
+pub fn is_ignore_file(path: &Path, root: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == ".gitignore" || name == ".vibeignore")
+        || path == root.join(".git").join("info").join("exclude")
+        || path == Config::global_ignore_path()
 }
 
-/// Simple pattern matching function supporting wildcards and directory patterns
-fn matches_pattern(path: &str, pattern: &str) -> bool {
-    if pattern.ends_with('/') {
-        // Directory pattern - matches the directory and everything under it
-        let dir_pattern: &str = &pattern[..pattern.len() - 1];
-        return path == dir_pattern || path.starts_with(&format!("{}/", dir_pattern));
+/// Load .gitignore and .vibeignore from `dir`, then recurse into
+/// subdirectories that are not themselves ignored
+fn add_directory_ignore_files(root: &Path, dir: &Path, matcher: &mut IgnoreMatcher) -> Result<()> {
+    let base = relative_path_string(dir, root).unwrap_or_default();
+    matcher.add_file(&dir.join(".gitignore"), &base, IgnoreLayer::Gitignore)?;
+    matcher.add_file(&dir.join(".vibeignore"), &base, IgnoreLayer::Vibeignore)?;
+
+    let mut subdirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => return Ok(()),
+    };
+    subdirs.sort();
+
+    for subdir in subdirs {
+        if !should_ignore_path(&subdir, root, matcher) {
+            add_directory_ignore_files(root, &subdir, matcher)?;
+        }
     }
+    Ok(())
+}
+
+/// `path` relative to `root` with `/` separators, or `None` if it is not
+/// under `root`
+pub fn relative_path_string(path: &Path, root: &Path) -> Option<String> {
+    let relative_path = path.strip_prefix(root).ok()?;
+    Some(
+        relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Whether a relative path lies inside .git or the .vibe store, which are
+/// always ignored regardless of any rules
+pub fn is_internal_path(rel_path: &str) -> bool {
+    let first_component = rel_path.split('/').next().unwrap_or_default();
+    first_component == ".git" || first_component == REPO_DIRNAME
+}
+
+/// Check if a path should be ignored based on the repo's ignore rules
+pub fn should_ignore_path(path: &Path, root: &Path, matcher: &IgnoreMatcher) -> bool {
+    let path_str: String = match relative_path_string(path, root) {
+        Some(rel) if !rel.is_empty() => rel,
+        _ => return false, // If not under root, don't ignore
+    };
 
-    if pattern.contains('*') {
-        // Simple wildcard matching
-        return matches_wildcard(path, pattern);
+    // Always ignore .git and the .vibe store itself
+    if is_internal_path(&path_str) {
+        return true;
     }
 
-    // Exact match
-    path == pattern
+    // A symlink to a directory is recorded as a link, so it only matches
+    // file patterns
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+    matcher.is_ignored(&path_str, is_dir)
 }
 
-fn matches_wildcard(text: &str, pattern: &str) -> bool {
-    let parts: Vec<&str> = pattern.split('*').collect();
-    if parts.len() == 1 {
-        return text == pattern;
+fn trim_unescaped_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') {
+        let before = &line[..end - 1];
+        // An odd number of backslashes means the space is escaped
+        let backslashes = before.len() - before.trim_end_matches('\\').len();
+        if backslashes % 2 == 1 {
+            break;
+        }
+        end -= 1;
     }
+    &line[..end]
+}
 
-    let mut current_pos: usize = 0;
-    for (i, part) in parts.iter().enumerate() {
-        if i == 0 {
-            // First part must match from the beginning
-            if !text[current_pos..].starts_with(part) {
-                return false;
+/// gitignore-style glob match: `*` and `?` never cross `/`, `**` spans
+/// directories, `[...]` is a character class and `\` escapes
+fn wildmatch(pattern: &[char], text: &[char]) -> bool {
+    let Some(&first) = pattern.first() else {
+        return text.is_empty();
+    };
+
+    match first {
+        '*' if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if rest.is_empty() {
+                return true;
             }
-            current_pos += part.len();
-        } else if i == parts.len() - 1 {
-            // Last part must match at the end
-            return text[current_pos..].ends_with(part);
-        } else {
-            // Middle parts
-            if let Some(pos) = text[current_pos..].find(part) {
-                current_pos += pos + part.len();
-            } else {
-                return false;
+            if rest[0] == '/' {
+                // "**/" matches zero or more leading directories
+                let rest = &rest[1..];
+                if wildmatch(rest, text) {
+                    return true;
+                }
+                return text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &c)| c == '/' && wildmatch(rest, &text[i + 1..]));
+            }
+            // "**" not bounded by slashes behaves like "*"
+            wildmatch(&pattern[1..], text)
+        }
+        '*' => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if wildmatch(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        '?' => match text.first() {
+            Some(&c) if c != '/' => wildmatch(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        '[' => match (
+            text.first(),
+            match_class(&pattern[1..], text.first().copied()),
+        ) {
+            (Some(&c), Some((true, consumed))) if c != '/' => {
+                wildmatch(&pattern[1 + consumed..], &text[1..])
+            }
+            (_, None) => {
+                // Unterminated class: treat '[' literally
+                text.first() == Some(&'[') && wildmatch(&pattern[1..], &text[1..])
             }
+            _ => false,
+        },
+        '\\' if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && wildmatch(&pattern[2..], &text[1..])
+        }
+        c => text.first() == Some(&c) && wildmatch(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Match `c` against a character class body (the part after `[`). Returns
+/// whether it matched and how many pattern chars the class used, including
+/// the closing `]`, or `None` if the class is not terminated.
+fn match_class(body: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(body.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < body.len() {
+        let mut lo = body[i];
+        if lo == ']' && !first {
+            let hit = c.is_some() && matched != negated;
+            return Some((hit, i + 1));
+        }
+        first = false;
+        if lo == '\\' && i + 1 < body.len() {
+            i += 1;
+            lo = body[i];
+        }
+        if i + 2 < body.len() && body[i + 1] == '-' && body[i + 2] != ']' {
+            let mut hi = body[i + 2];
+            i += 2;
+            if hi == '\\' && i + 1 < body.len() {
+                i += 1;
+                hi = body[i];
+            }
+            if c.is_some_and(|c| lo <= c && c <= hi) {
+                matched = true;
+            }
+        } else if c == Some(lo) {
+            matched = true;
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob_matches(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        wildmatch(&pattern, &text)
+    }
+
+    /// A matcher built from ignore file contents, each applying below `base`
+    fn matcher(files: &[(&str, &str)]) -> IgnoreMatcher {
+        let mut matcher = IgnoreMatcher::default();
+        for (base, content) in files {
+            for (index, line) in content.lines().enumerate() {
+                if let Some(mut rule) = IgnoreRule::parse(line) {
+                    rule.line = index + 1;
+                    rule.base = base.to_string();
+                    matcher.rules.push(rule);
+                }
+            }
+        }
+        matcher
+    }
+
+    #[test]
+    fn wildmatch_literals_and_wildcards() {
+        assert!(glob_matches("foo", "foo"));
+        assert!(!glob_matches("foo", "bar"));
+        assert!(!glob_matches("foo", "foobar"));
+        assert!(glob_matches("f*", "foo"));
+        assert!(glob_matches("*.rs", ".rs"));
+        assert!(!glob_matches("*f", "foo"));
+        assert!(glob_matches("??", "ab"));
+        assert!(!glob_matches("?", "ab"));
+        assert!(!glob_matches("*", "foo/bar"));
+        assert!(!glob_matches("foo?bar", "foo/bar"));
+        assert!(glob_matches("doc/*.txt", "doc/notes.txt"));
+        assert!(!glob_matches("doc/*.txt", "doc/server/notes.txt"));
+    }
+
+    #[test]
+    fn wildmatch_double_star() {
+        assert!(glob_matches("**/foo", "foo"));
+        assert!(glob_matches("**/foo", "a/b/foo"));
+        assert!(!glob_matches("**/foo", "a/foobar"));
+        assert!(glob_matches("foo/**", "foo/a/b"));
+        assert!(!glob_matches("foo/**", "bar/a"));
+        assert!(glob_matches("a/**/b", "a/b"));
+        assert!(glob_matches("a/**/b", "a/x/y/b"));
+        assert!(!glob_matches("a/**/b", "a/xb"));
+        // Not bounded by slashes, ** is an ordinary *
+        assert!(glob_matches("a**b", "axxb"));
+        assert!(!glob_matches("a**b", "a/b"));
+    }
+
+    #[test]
+    fn wildmatch_classes_and_escapes() {
+        assert!(glob_matches("[a-c]at", "bat"));
+        assert!(!glob_matches("[a-c]at", "dat"));
+        assert!(glob_matches("[!a-c]at", "dat"));
+        assert!(glob_matches("[^a-c]at", "dat"));
+        assert!(!glob_matches("[!a-c]at", "cat"));
+        assert!(glob_matches("[]]", "]"));
+        assert!(glob_matches("[!]]", "a"));
+        assert!(glob_matches("[\\]]", "]"));
+        assert!(!glob_matches("[/]", "/"));
+        assert!(glob_matches("\\*", "*"));
+        assert!(!glob_matches("\\*", "x"));
+        assert!(glob_matches("\\[ab]", "[ab]"));
+        // An unterminated class is a literal '['
+        assert!(glob_matches("[abc", "[abc"));
+        assert!(!glob_matches("[abc", "a"));
+    }
+
+    #[test]
+    fn match_class_reports_length_and_termination() {
+        let body: Vec<char> = "a-c]rest".chars().collect();
+        assert_eq!(match_class(&body, Some('b')), Some((true, 4)));
+        assert_eq!(match_class(&body, Some('d')), Some((false, 4)));
+        let negated: Vec<char> = "!xy]".chars().collect();
+        assert_eq!(match_class(&negated, Some('z')), Some((true, 4)));
+        assert_eq!(match_class(&negated, None), Some((false, 4)));
+        let unterminated: Vec<char> = "abc".chars().collect();
+        assert_eq!(match_class(&unterminated, Some('a')), None);
+    }
+
+    #[test]
+    fn parse_skips_blank_lines_and_comments() {
+        assert!(IgnoreRule::parse("").is_none());
+        assert!(IgnoreRule::parse("   ").is_none());
+        assert!(IgnoreRule::parse("# comment").is_none());
+        assert!(IgnoreRule::parse("/").is_none());
+        let escaped = IgnoreRule::parse("\\#file").unwrap();
+        assert!(escaped.matches("#file", false));
+    }
+
+    #[test]
+    fn parse_flags() {
+        let rule = IgnoreRule::parse("!build/").unwrap();
+        assert!(rule.is_negated());
+        assert!(rule.dir_only);
+        assert!(!rule.anchored);
+        assert!(rule.matches("src/build", true));
+        assert!(!rule.matches("src/build", false));
+
+        let rule = IgnoreRule::parse("/target").unwrap();
+        assert!(rule.anchored);
+        assert!(rule.matches("target", true));
+        assert!(!rule.matches("crates/target", true));
+
+        let rule = IgnoreRule::parse("doc/*.txt").unwrap();
+        assert!(rule.anchored);
+        assert!(rule.matches("doc/a.txt", false));
+        assert!(!rule.matches("x/doc/a.txt", false));
+    }
+
+    #[test]
+    fn parse_trailing_whitespace() {
+        let rule = IgnoreRule::parse("foo  \r").unwrap();
+        assert_eq!(rule.glob.iter().collect::<String>(), "foo");
+        assert!(rule.matches("foo", false));
+        let rule = IgnoreRule::parse("foo\\ ").unwrap();
+        assert!(rule.matches("foo ", false));
+        assert!(!rule.matches("foo", false));
+    }
+
+    #[test]
+    fn explain_returns_last_matching_rule() {
+        let matcher = matcher(&[("", "*.log\n!important.log\n")]);
+        assert_eq!(matcher.explain("debug.log", false).unwrap().line, 1);
+        let rule = matcher.explain("logs/important.log", false).unwrap();
+        assert_eq!(rule.line, 2);
+        assert!(rule.is_negated());
+        assert!(!matcher.is_ignored("logs/important.log", false));
+        assert!(matcher.explain("main.rs", false).is_none());
+    }
+
+    #[test]
+    fn explain_files_in_ignored_directories_stay_ignored() {
+        let matcher = matcher(&[("", "build/\n!build/keep.txt\n")]);
+        let rule = matcher.explain("build/keep.txt", false).unwrap();
+        assert_eq!(rule.text, "build/");
+        assert!(matcher.is_ignored("build/keep.txt", false));
+    }
+
+    #[test]
+    fn nested_ignore_files_apply_below_their_directory() {
+        let matcher = matcher(&[("", "*.tmp\n"), ("sub", "!keep.tmp\n/local\n")]);
+        assert!(matcher.is_ignored("other/keep.tmp", false));
+        assert!(!matcher.is_ignored("sub/keep.tmp", false));
+        assert!(matcher.is_ignored("sub/local", false));
+        assert!(!matcher.is_ignored("local", false));
+        assert!(!matcher.is_ignored("sub/deeper/local", false));
+    }
+
+    #[test]
+    fn read_ignore_rules_layers_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.swp\n").unwrap();
+        fs::write(root.join(".gitignore"), "*.log\nsecrets/\n").unwrap();
+        fs::write(root.join(".vibeignore"), "!keep.log\nbig.bin\n").unwrap();
+        fs::write(root.join("sub/.gitignore"), "!*.log\n").unwrap();
+
+        let matcher = read_ignore_rules(root).unwrap();
+        assert!(matcher.is_ignored("notes.swp", false));
+        assert!(matcher.is_ignored("debug.log", false));
+        // .vibeignore outranks every .gitignore
+        assert!(!matcher.is_ignored("keep.log", false));
+        assert!(matcher.is_ignored("big.bin", false));
+        // A deeper .gitignore outranks a shallower one
+        assert!(!matcher.is_ignored("sub/debug.log", false));
+        let rule = matcher.explain("secrets/key", false).unwrap();
+        assert_eq!(rule.source, root.join(".gitignore"));
+        assert_eq!(rule.line, 2);
+    }
+
+    #[test]
+    fn rust_gitignore() {
+        let matcher = matcher(&[("", "# Generated by Cargo\n/target/\n\n**/*.rs.bk\n*.pdb\n")]);
+        assert!(matcher.is_ignored("target", true));
+        assert!(matcher.is_ignored("target/debug/app", false));
+        assert!(!matcher.is_ignored("crates/cli/target", true));
+        assert!(matcher.is_ignored("src/main.rs.bk", false));
+        assert!(matcher.is_ignored("app.pdb", false));
+        assert!(!matcher.is_ignored("src/main.rs", false));
+    }
+
+    #[test]
+    fn node_gitignore() {
+        let matcher = matcher(&[(
+            "",
+            "node_modules/\nnpm-debug.log*\n.env\n.env.*\n!.env.example\ndist\n",
+        )]);
+        assert!(matcher.is_ignored("node_modules/react/index.js", false));
+        assert!(matcher.is_ignored("packages/ui/node_modules", true));
+        assert!(matcher.is_ignored("npm-debug.log.1234", false));
+        assert!(matcher.is_ignored(".env", false));
+        assert!(matcher.is_ignored(".env.local", false));
+        assert!(!matcher.is_ignored(".env.example", false));
+        assert!(matcher.is_ignored("dist/bundle.js", false));
+        assert!(!matcher.is_ignored("src/index.js", false));
+    }
+
+    #[test]
+    fn python_gitignore() {
+        let matcher = matcher(&[(
+            "",
+            "__pycache__/\n*.py[cod]\n*$py.class\n.venv\n*.egg-info/\n",
+        )]);
+        assert!(matcher.is_ignored("pkg/__pycache__/mod.cpython-312.pyc", false));
+        assert!(matcher.is_ignored("pkg/mod.pyc", false));
+        assert!(matcher.is_ignored("pkg/mod.pyd", false));
+        assert!(!matcher.is_ignored("pkg/mod.py", false));
+        assert!(matcher.is_ignored("Foo$py.class", false));
+        assert!(matcher.is_ignored(".venv/bin/python", false));
+        assert!(matcher.is_ignored("vibesnap.egg-info", true));
+        assert!(!matcher.is_ignored("vibesnap.egg-info", false));
+    }
+
+    #[test]
+    fn internal_paths() {
+        assert!(is_internal_path(".git/config"));
+        assert!(is_internal_path(REPO_DIRNAME));
+        assert!(!is_internal_path("src/.git"));
+        assert!(!is_internal_path(".github/workflows"));
+    }
+
+    #[test]
+    fn symlinked_directories_do_not_match_directory_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        std::os::unix::fs::symlink("real", root.join("link")).unwrap();
+        let matcher = matcher(&[("", "real/\nlink/\n")]);
+
+        assert!(should_ignore_path(&root.join("real"), root, &matcher));
+        assert!(!should_ignore_path(&root.join("link"), root, &matcher));
+    }
+
+    #[test]
+    fn ignore_files_are_recognised() {
+        let root = Path::new("/repo");
+        for path in [
+            "/repo/.gitignore",
+            "/repo/src/.vibeignore",
+            "/repo/.git/info/exclude",
+        ] {
+            assert!(is_ignore_file(Path::new(path), root), "{}", path);
+        }
+        for path in ["/repo/src/main.rs", "/repo/exclude", "/repo/.vibe/HEAD"] {
+            assert!(!is_ignore_file(Path::new(path), root), "{}", path);
         }
     }
-    true
 }
@@ -0,0 +1,223 @@
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::constants::{INDEX_FILENAME, REPO_DIRNAME};
+use super::utils::write_atomic;
+
+/// Entries modified this close to the start of the scan that hashed them may
+/// have changed again within the filesystem's timestamp granularity, so their
+/// cached hash is never trusted
+const RACY_WINDOW_NANOS: i64 = 2_000_000_000;
+
+/// Stat information recorded for a file when it was last hashed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub size: u64,
+    pub mtime_nanos: i64,
+    pub inode: u64,
+    pub hash: String,
+}
+
+impl IndexEntry {
+    fn from_metadata(metadata: &fs::Metadata, hash: String) -> Self {
+        IndexEntry {
+            size: metadata.len(),
+            mtime_nanos: mtime_nanos(metadata),
+            inode: inode(metadata),
+            hash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct IndexFile {
+    /// When the scan that produced these entries started; a file changed
+    /// after hashing has a later mtime than this
+    scanned_at_nanos: i64,
+    entries: HashMap<String, IndexEntry>,
+}
+
+/// Persistent stat cache in `.vibe/index.json` mapping relative paths to the
+/// hash of their content, so unchanged files need not be re-read
+#[derive(Debug, Default)]
+pub struct StatCache {
+    index: IndexFile,
+    started_at_nanos: i64,
+    seen: HashSet<String>,
+    changed: bool,
+}
+
+impl StatCache {
+    /// Load the index, starting empty if it is missing, unreadable or
+    /// `rehash` is requested
+    pub fn load(root: &Path, rehash: bool) -> Self {
+        let index = if rehash {
+            IndexFile::default()
+        } else {
+            fs::read_to_string(index_path(root))
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default()
+        };
+        StatCache {
+            index,
+            started_at_nanos: now_nanos(),
+            seen: HashSet::new(),
+            changed: rehash,
+        }
+    }
+
+    /// The cached hash for `rel_path` if its stat data is unchanged and the
+    /// entry is not racy
+    pub fn lookup(&mut self, rel_path: &str, metadata: &fs::Metadata) -> Option<String> {
+        self.seen.insert(rel_path.to_string());
+        let entry = self.index.entries.get(rel_path)?;
+        let current = IndexEntry::from_metadata(metadata, entry.hash.clone());
+        let racy = current.mtime_nanos + RACY_WINDOW_NANOS > self.index.scanned_at_nanos;
+        if racy {
+            // Rehashing it now lets the next save move the scan time forward
+            self.changed = true;
+            return None;
+        }
+        if current != *entry {
+            return None;
+        }
+        Some(entry.hash.clone())
+    }
+
+    pub fn update(&mut self, rel_path: &str, metadata: &fs::Metadata, hash: &str) {
+        self.seen.insert(rel_path.to_string());
+        let entry = IndexEntry::from_metadata(metadata, hash.to_string());
+        if self.index.entries.get(rel_path) != Some(&entry) {
+            self.index.entries.insert(rel_path.to_string(), entry);
+            self.changed = true;
+        }
+    }
+
+    /// Drop entries for paths not looked up since loading. Only call this
+    /// after visiting the whole working tree.
+    pub fn prune_unseen(&mut self) {
+        let before = self.index.entries.len();
+        let seen = &self.seen;
+        self.index.entries.retain(|path, _| seen.contains(path));
+        self.changed |= self.index.entries.len() != before;
+    }
+
+    /// Write the index back if anything changed, via a temp file and rename
+    /// so a concurrent reader never sees a partial file
+    pub fn save(mut self, root: &Path) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.index.scanned_at_nanos = self.started_at_nanos;
+        let content = serde_json::to_string(&self.index)
+            .map_err(|e| crate::error::AppError::Generic(e.to_string()))?;
+        write_atomic(&index_path(root), content.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn index_path(root: &Path) -> PathBuf {
+    root.join(REPO_DIRNAME).join(INDEX_FILENAME)
+}
+
+fn now_nanos() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+fn mtime_nanos(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A repo directory with one file whose mtime is `age` in the past
+    fn repo_with_file(age: Duration) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "hello").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        (dir, path)
+    }
+
+    fn cache_hash(root: &Path, path: &Path) -> Option<String> {
+        let metadata = fs::symlink_metadata(path).unwrap();
+        StatCache::load(root, false).lookup("a.txt", &metadata)
+    }
+
+    fn record(root: &Path, path: &Path, hash: &str) {
+        let metadata = fs::symlink_metadata(path).unwrap();
+        let mut cache = StatCache::load(root, false);
+        cache.update("a.txt", &metadata, hash);
+        cache.save(root).unwrap();
+    }
+
+    #[test]
+    fn unchanged_file_is_served_from_cache() {
+        let (dir, path) = repo_with_file(Duration::from_secs(60));
+        record(dir.path(), &path, "abc");
+        assert_eq!(cache_hash(dir.path(), &path), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn file_modified_close_to_the_scan_is_racy() {
+        let (dir, path) = repo_with_file(Duration::ZERO);
+        record(dir.path(), &path, "abc");
+        assert_eq!(cache_hash(dir.path(), &path), None);
+    }
+
+    #[test]
+    fn changed_stat_data_misses() {
+        let (dir, path) = repo_with_file(Duration::from_secs(60));
+        record(dir.path(), &path, "abc");
+        fs::write(&path, "hello, world").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(cache_hash(dir.path(), &path), None);
+    }
+
+    #[test]
+    fn rehash_and_prune_drop_entries() {
+        let (dir, path) = repo_with_file(Duration::from_secs(60));
+        record(dir.path(), &path, "abc");
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        assert_eq!(
+            StatCache::load(dir.path(), true).lookup("a.txt", &metadata),
+            None
+        );
+
+        let mut cache = StatCache::load(dir.path(), false);
+        cache.prune_unseen();
+        cache.save(dir.path()).unwrap();
+        assert_eq!(cache_hash(dir.path(), &path), None);
+    }
+}
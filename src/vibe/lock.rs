@@ -0,0 +1,228 @@
+use crate::error::{AppError, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::constants::{LOCK_FILENAME, REPO_DIRNAME};
+use super::utils::{TEMP_FILE_INFIX, is_process_running};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How old a lock whose holder cannot be read must be before it is treated as
+/// abandoned. Older versions created the file before writing the holder, so a
+/// crash in between left it empty.
+const ABANDONED_LOCK_AGE: Duration = Duration::from_secs(10);
+
+/// Exclusive hold on a repository, released when dropped. Every command that
+/// changes HEAD, tracks, checkpoints or the working tree takes it, so the
+/// watcher and manual commands never interleave.
+#[derive(Debug)]
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Take the lock, waiting up to `timeout` for another holder to finish.
+    /// A lock left by a process that is no longer running is broken.
+    pub fn acquire(root: &Path, command: &str, timeout: Duration) -> Result<RepoLock> {
+        let path = root.join(REPO_DIRNAME).join(LOCK_FILENAME);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(lock) = try_take(&path, command)? {
+                return Ok(lock);
+            }
+
+            if Instant::now() >= deadline {
+                let description = match read_holder(&path) {
+                    Some((pid, command)) => format!("'{}' (pid {})", command, pid),
+                    None => "another vibesnap process".to_string(),
+                };
+                return Err(AppError::RepoLocked(
+                    description,
+                    path.display().to_string(),
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Take the lock only if nobody is holding it right now
+    pub fn try_acquire(root: &Path, command: &str) -> Result<Option<RepoLock>> {
+        try_take(&root.join(REPO_DIRNAME).join(LOCK_FILENAME), command)
+    }
+}
+
+fn try_take(path: &Path, command: &str) -> Result<Option<RepoLock>> {
+    loop {
+        // The holder is written before the lock is linked into place, so
+        // nobody ever sees a lock without one
+        let temp = temp_path(path);
+        fs::write(&temp, format!("{} {}\n", std::process::id(), command))?;
+        let linked = fs::hard_link(&temp, path);
+        fs::remove_file(&temp)?;
+        match linked {
+            Ok(()) => {
+                return Ok(Some(RepoLock {
+                    path: path.to_path_buf(),
+                }));
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        match read_holder(path) {
+            // Stale: the holder died without cleaning up
+            Some((pid, _)) if !is_process_running(pid) => break_stale_lock(path, Some(pid))?,
+            None if is_abandoned(path) => break_stale_lock(path, None)?,
+            _ => return Ok(None),
+        }
+    }
+}
+
+fn is_abandoned(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= ABANDONED_LOCK_AGE)
+}
+
+/// A name next to the lock that only this call uses
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    path.with_file_name(format!(
+        "{}{}{}-{}",
+        LOCK_FILENAME,
+        TEMP_FILE_INFIX,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Remove a lock left behind by the dead process `pid`, or with no readable
+/// holder when `pid` is `None`. Checking the holder
+/// and deleting the file cannot be done in one step, and in between another
+/// process may have broken the same lock and taken a fresh one. So the file
+/// is first renamed to a name only we use, which at most one process can do,
+/// and checked again there; a lock that turns out not to be the stale one is
+/// linked back into place (the same file, so its holder never notices).
+fn break_stale_lock(path: &Path, pid: Option<i32>) -> Result<()> {
+    let claimed = temp_path(path);
+    match fs::rename(path, &claimed) {
+        Ok(()) => {}
+        // Someone else broke it first
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    if read_holder(&claimed).map(|(holder, _)| holder) != pid {
+        // Fails only if a third process has taken the lock meanwhile, in
+        // which case that one is the holder
+        let _ = fs::hard_link(&claimed, path);
+    }
+    fs::remove_file(&claimed)?;
+    Ok(())
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        // Only remove the lock if it is still ours; it may already be gone
+        // (e.g. `reset` deletes the whole .vibe directory)
+        if read_holder(&self.path).is_some_and(|(pid, _)| pid == std::process::id() as i32) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// PID and command recorded in a lock file
+fn read_holder(path: &Path) -> Option<(i32, String)> {
+    let content = fs::read_to_string(path).ok()?;
+    let (pid, command) = content.trim().split_once(' ')?;
+    Some((pid.parse().ok()?, command.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        dir
+    }
+
+    fn lock_path(root: &Path) -> PathBuf {
+        root.join(REPO_DIRNAME).join(LOCK_FILENAME)
+    }
+
+    #[test]
+    fn lock_is_released_on_drop() {
+        let dir = repo();
+        let root = dir.path();
+        let lock = RepoLock::acquire(root, "snap", Duration::ZERO).unwrap();
+        assert_eq!(
+            read_holder(&lock_path(root)),
+            Some((std::process::id() as i32, "snap".to_string()))
+        );
+        drop(lock);
+        assert!(!lock_path(root).exists());
+        RepoLock::acquire(root, "restore", Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn lock_held_by_a_live_process_times_out() {
+        let dir = repo();
+        let root = dir.path();
+        let live_pid = std::os::unix::process::parent_id();
+        fs::write(lock_path(root), format!("{} watch\n", live_pid)).unwrap();
+
+        let err = RepoLock::acquire(root, "snap", Duration::from_millis(150)).unwrap_err();
+        assert!(matches!(err, AppError::RepoLocked(holder, _) if holder.contains("'watch'")));
+        assert!(lock_path(root).exists());
+    }
+
+    #[test]
+    fn stale_lock_is_broken() {
+        let dir = repo();
+        let root = dir.path();
+        fs::write(lock_path(root), format!("{} snap\n", i32::MAX)).unwrap();
+
+        let _lock = RepoLock::acquire(root, "restore", Duration::ZERO).unwrap();
+        assert_eq!(
+            read_holder(&lock_path(root)),
+            Some((std::process::id() as i32, "restore".to_string()))
+        );
+    }
+
+    #[test]
+    fn empty_lock_is_broken_once_abandoned() {
+        let dir = repo();
+        let root = dir.path();
+        let file = fs::File::create(lock_path(root)).unwrap();
+
+        // It may still be being written
+        assert!(RepoLock::try_acquire(root, "snap").unwrap().is_none());
+
+        file.set_modified(std::time::SystemTime::now() - 2 * ABANDONED_LOCK_AGE)
+            .unwrap();
+        let _lock = RepoLock::try_acquire(root, "snap").unwrap().unwrap();
+        assert_eq!(
+            read_holder(&lock_path(root)),
+            Some((std::process::id() as i32, "snap".to_string()))
+        );
+    }
+
+    #[test]
+    fn try_acquire_does_not_wait() {
+        let dir = repo();
+        let root = dir.path();
+        let held = RepoLock::try_acquire(root, "watch").unwrap();
+        assert!(held.is_some());
+        assert!(RepoLock::try_acquire(root, "snap").unwrap().is_none());
+        drop(held);
+        assert!(RepoLock::try_acquire(root, "snap").unwrap().is_some());
+    }
+}
@@ -1,7 +1,18 @@
+pub mod checkpoint;
 pub mod constants;
 pub mod db;
+pub mod entry;
+pub mod fsck;
+pub mod gc;
 pub mod ignore;
+pub mod index;
+pub mod lock;
 pub mod objects;
+pub mod oplog;
 pub mod repo;
+pub mod retention;
+pub mod revision;
 pub mod snapshot;
+pub mod status;
+pub mod tree;
 pub mod utils;
@@ -1,37 +1,316 @@
 use crate::error::{AppError, Result};
 use sha2::{Digest, Sha256};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 use super::constants::{OBJECTS_DIRNAME, REPO_DIRNAME};
+use super::utils::write_atomic;
 
-fn hash_file_content(content: &[u8]) -> String {
+/// Every object written by this version starts with this magic. 0xFF never
+/// begins valid UTF-8, so text files stored raw by older versions cannot be
+/// mistaken for it.
+const OBJECT_MAGIC: &[u8; 3] = b"\xffVO";
+const OBJECT_FORMAT_VERSION: u8 = 1;
+/// Magic, format version and encoding byte
+const OBJECT_HEADER_LEN: usize = OBJECT_MAGIC.len() + 2;
+const ZSTD_LEVEL: i32 = 3;
+
+/// How an object's payload is stored after its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectEncoding {
+    /// Legacy object with no header: the file is the content itself
+    Legacy,
+    /// Header followed by the uncompressed content
+    Raw,
+    /// Header followed by a zstd frame
+    Zstd,
+}
+
+impl ObjectEncoding {
+    fn tag(self) -> u8 {
+        match self {
+            ObjectEncoding::Legacy | ObjectEncoding::Raw => 0,
+            ObjectEncoding::Zstd => 1,
+        }
+    }
+}
+
+pub fn hash_file_content(content: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(content);
     hex::encode(hasher.finalize())
 }
 
+fn objects_dir(root: &Path) -> PathBuf {
+    root.join(REPO_DIRNAME).join(OBJECTS_DIRNAME)
+}
+
+/// Where the object for `hash` is written: fanned out by its first two hex
+/// digits, like git (`objects/ab/cdef…`)
+pub fn object_path(root: &Path, hash: &str) -> PathBuf {
+    match (hash.get(..2), hash.get(2..)) {
+        (Some(fan), Some(rest)) if !rest.is_empty() => objects_dir(root).join(fan).join(rest),
+        _ => legacy_object_path(root, hash),
+    }
+}
+
+/// Where older versions wrote the object for `hash`: directly in `objects/`
+pub fn legacy_object_path(root: &Path, hash: &str) -> PathBuf {
+    objects_dir(root).join(hash)
+}
+
+/// The file holding `hash`, in whichever layout it was stored
+pub fn find_object(root: &Path, hash: &str) -> Option<PathBuf> {
+    [object_path(root, hash), legacy_object_path(root, hash)]
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
 pub fn store_content_in_objects(root: &Path, content: &[u8]) -> Result<String> {
     let hash: String = hash_file_content(content);
-    let objects_dir: PathBuf = root.join(REPO_DIRNAME).join(OBJECTS_DIRNAME);
-    fs::create_dir_all(&objects_dir)?;
-
-    let object_path = objects_dir.join(&hash);
 
     // Only write if object doesn't already exist (deduplication)
-    if !object_path.exists() {
-        fs::write(object_path, content)?;
+    if find_object(root, &hash).is_none() {
+        let object_path = object_path(root, &hash);
+        if let Some(fan_dir) = object_path.parent() {
+            fs::create_dir_all(fan_dir)?;
+        }
+        write_atomic(&object_path, &encode_object(content)?)?;
     }
 
     Ok(hash)
 }
 
+pub fn object_exists(root: &Path, hash: &str) -> bool {
+    find_object(root, hash).is_some()
+}
+
 pub fn read_content_from_objects(root: &Path, hash: &str) -> Result<Vec<u8>> {
-    let object_path = root.join(REPO_DIRNAME).join(OBJECTS_DIRNAME).join(hash);
+    let object_path =
+        find_object(root, hash).ok_or_else(|| AppError::ObjectNotFound(hash.to_string()))?;
 
-    if !object_path.exists() {
-        return Err(AppError::ObjectNotFound(hash.to_string()));
+    let data = fs::read(object_path)?;
+    decode_object(hash, &data)
+}
+
+/// Every object in the store as (hash, file), in both the fanned-out and the
+/// legacy flat layout, sorted by hash
+pub fn list_objects(root: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut objects = Vec::new();
+    let dir = objects_dir(root);
+    if !dir.is_dir() {
+        return Ok(objects);
+    }
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+        if file_type.is_file() && is_object_hash(&name) {
+            objects.push((name, entry.path()));
+        } else if file_type.is_dir() && name.len() == 2 && is_hex(&name) {
+            for inner in fs::read_dir(entry.path())? {
+                let inner = inner?;
+                let hash = format!("{}{}", name, inner.file_name().to_string_lossy());
+                if inner.file_type()?.is_file() && is_object_hash(&hash) {
+                    objects.push((hash, inner.path()));
+                }
+            }
+        }
     }
+    objects.sort();
+    Ok(objects)
+}
+
+fn is_hex(name: &str) -> bool {
+    name.bytes().all(|b| b.is_ascii_hexdigit())
+}
 
-    fs::read(object_path).map_err(AppError::IoError)
+fn is_object_hash(name: &str) -> bool {
+    name.len() == 64 && is_hex(name)
+}
+
+/// Frame `content` with an object header, compressing it unless that would
+/// not make it any smaller
+pub fn encode_object(content: &[u8]) -> Result<Vec<u8>> {
+    let compressed = zstd::encode_all(content, ZSTD_LEVEL)?;
+    let (encoding, payload) = if compressed.len() < content.len() {
+        (ObjectEncoding::Zstd, compressed.as_slice())
+    } else {
+        (ObjectEncoding::Raw, content)
+    };
+
+    let mut data = Vec::with_capacity(OBJECT_HEADER_LEN + payload.len());
+    data.extend_from_slice(OBJECT_MAGIC);
+    data.push(OBJECT_FORMAT_VERSION);
+    data.push(encoding.tag());
+    data.extend_from_slice(payload);
+    Ok(data)
+}
+
+/// The encoding of a stored object, judged from its header
+pub fn object_encoding(hash: &str, data: &[u8]) -> Result<ObjectEncoding> {
+    if !data.starts_with(OBJECT_MAGIC) {
+        return Ok(ObjectEncoding::Legacy);
+    }
+    match data.get(OBJECT_MAGIC.len()..OBJECT_HEADER_LEN) {
+        Some([OBJECT_FORMAT_VERSION, 0]) => Ok(ObjectEncoding::Raw),
+        Some([OBJECT_FORMAT_VERSION, 1]) => Ok(ObjectEncoding::Zstd),
+        Some([OBJECT_FORMAT_VERSION, tag]) => Err(AppError::CorruptObject(
+            hash.to_string(),
+            format!("unknown encoding {}", tag),
+        )),
+        Some([version, _]) => Err(AppError::CorruptObject(
+            hash.to_string(),
+            format!(
+                "object format version {} is newer than this vibesnap supports",
+                version
+            ),
+        )),
+        _ => Err(AppError::CorruptObject(
+            hash.to_string(),
+            "truncated header".to_string(),
+        )),
+    }
+}
+
+/// The encoding of the object stored at `path`, reading only its header
+pub fn read_object_encoding(hash: &str, path: &Path) -> Result<ObjectEncoding> {
+    let mut header = Vec::with_capacity(OBJECT_HEADER_LEN);
+    fs::File::open(path)?
+        .take(OBJECT_HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    object_encoding(hash, &header)
+}
+
+/// Recover the original content from a stored object, legacy or framed
+pub fn decode_object(hash: &str, data: &[u8]) -> Result<Vec<u8>> {
+    match object_encoding(hash, data)? {
+        ObjectEncoding::Legacy => Ok(data.to_vec()),
+        ObjectEncoding::Raw => Ok(data[OBJECT_HEADER_LEN..].to_vec()),
+        ObjectEncoding::Zstd => zstd::decode_all(&data[OBJECT_HEADER_LEN..])
+            .map_err(|e| AppError::CorruptObject(hash.to_string(), e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressible_content_round_trips_through_zstd() {
+        let content = "the same line over and over\n".repeat(200).into_bytes();
+        let data = encode_object(&content).unwrap();
+        assert!(data.len() < content.len());
+        assert_eq!(object_encoding("h", &data).unwrap(), ObjectEncoding::Zstd);
+        assert_eq!(decode_object("h", &data).unwrap(), content);
+    }
+
+    #[test]
+    fn incompressible_content_is_stored_raw() {
+        let content = b"tiny".to_vec();
+        let data = encode_object(&content).unwrap();
+        assert_eq!(object_encoding("h", &data).unwrap(), ObjectEncoding::Raw);
+        assert_eq!(decode_object("h", &data).unwrap(), content);
+    }
+
+    #[test]
+    fn headerless_objects_are_read_as_legacy() {
+        let content = b"written by an older vibesnap".to_vec();
+        assert_eq!(
+            object_encoding("h", &content).unwrap(),
+            ObjectEncoding::Legacy
+        );
+        assert_eq!(decode_object("h", &content).unwrap(), content);
+    }
+
+    #[test]
+    fn bad_headers_are_reported_as_corrupt() {
+        let mut newer = OBJECT_MAGIC.to_vec();
+        newer.extend_from_slice(&[OBJECT_FORMAT_VERSION + 1, 0]);
+        let mut unknown = OBJECT_MAGIC.to_vec();
+        unknown.extend_from_slice(&[OBJECT_FORMAT_VERSION, 9]);
+        let mut bad_frame = OBJECT_MAGIC.to_vec();
+        bad_frame.extend_from_slice(&[OBJECT_FORMAT_VERSION, 1, 0, 1, 2]);
+
+        for data in [newer, unknown, OBJECT_MAGIC.to_vec()] {
+            assert!(matches!(
+                object_encoding("h", &data),
+                Err(AppError::CorruptObject(..))
+            ));
+        }
+        assert!(matches!(
+            decode_object("h", &bad_frame),
+            Err(AppError::CorruptObject(..))
+        ));
+    }
+
+    #[test]
+    fn encoding_is_read_from_the_header_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("object");
+        let compressible = "again and again\n".repeat(200).into_bytes();
+        for (data, expected) in [
+            (encode_object(&compressible).unwrap(), ObjectEncoding::Zstd),
+            (encode_object(b"tiny").unwrap(), ObjectEncoding::Raw),
+            (b"legacy content".to_vec(), ObjectEncoding::Legacy),
+            (Vec::new(), ObjectEncoding::Legacy),
+        ] {
+            fs::write(&path, &data).unwrap();
+            assert_eq!(read_object_encoding("h", &path).unwrap(), expected);
+        }
+        fs::write(&path, OBJECT_MAGIC).unwrap();
+        assert!(read_object_encoding("h", &path).is_err());
+    }
+
+    #[test]
+    fn stored_objects_are_deduplicated_and_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let hash = store_content_in_objects(root, b"hello").unwrap();
+        assert_eq!(hash, hash_file_content(b"hello"));
+        assert_eq!(store_content_in_objects(root, b"hello").unwrap(), hash);
+        assert_eq!(read_content_from_objects(root, &hash).unwrap(), b"hello");
+        assert!(matches!(
+            read_content_from_objects(root, &hash_file_content(b"other")),
+            Err(AppError::ObjectNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn objects_are_fanned_out_and_legacy_ones_still_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let hash = store_content_in_objects(root, b"new").unwrap();
+        let path = object_path(root, &hash);
+        assert!(path.is_file());
+        assert_eq!(
+            path.strip_prefix(objects_dir(root)).unwrap(),
+            Path::new(&hash[..2]).join(&hash[2..])
+        );
+
+        // An object written flat by an older version
+        let legacy_hash = hash_file_content(b"old");
+        fs::write(legacy_object_path(root, &legacy_hash), b"old").unwrap();
+        assert_eq!(
+            find_object(root, &legacy_hash),
+            Some(legacy_object_path(root, &legacy_hash))
+        );
+        assert_eq!(
+            read_content_from_objects(root, &legacy_hash).unwrap(),
+            b"old"
+        );
+        // Storing the same content again reuses the legacy copy
+        store_content_in_objects(root, b"old").unwrap();
+        assert!(!object_path(root, &legacy_hash).exists());
+
+        fs::write(objects_dir(root).join("stray.tmp"), b"").unwrap();
+        fs::create_dir_all(objects_dir(root).join("zz")).unwrap();
+        let mut expected = vec![
+            (hash.clone(), path),
+            (legacy_hash.clone(), legacy_object_path(root, &legacy_hash)),
+        ];
+        expected.sort();
+        assert_eq!(list_objects(root).unwrap(), expected);
+    }
 }
@@ -0,0 +1,297 @@
+use crate::error::{AppError, Result};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::repo::{read_head, write_head};
+
+/// HEAD plus every track head, i.e. everything an operation can move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoState {
+    pub track: String,
+    pub checkpoint_id: Option<String>,
+    pub tracks: BTreeMap<String, Option<String>>,
+}
+
+impl RepoState {
+    pub fn capture(root: &Path, conn: &Connection) -> Result<Self> {
+        let (track, checkpoint_id) = read_head(root)?;
+        let mut stmt = conn.prepare("SELECT name, head FROM tracks ORDER BY name")?;
+        let tracks = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+            })?
+            .collect::<std::result::Result<BTreeMap<_, _>, _>>()?;
+        Ok(RepoState {
+            track,
+            checkpoint_id,
+            tracks,
+        })
+    }
+
+    /// HEAD in the same "track [id]" form used by the HEAD file
+    fn head_string(&self) -> String {
+        match &self.checkpoint_id {
+            Some(id) => format!("{} {}", self.track, id),
+            None => self.track.clone(),
+        }
+    }
+
+    fn from_columns(head: &str, tracks_json: &str) -> Result<Self> {
+        let mut parts = head.split_whitespace();
+        let track = parts.next().ok_or(AppError::InvalidHead)?.to_string();
+        let checkpoint_id = parts.next().map(|s| s.to_string());
+        let tracks = serde_json::from_str(tracks_json)
+            .map_err(|e| AppError::Generic(format!("Corrupt operation log entry: {}", e)))?;
+        Ok(RepoState {
+            track,
+            checkpoint_id,
+            tracks,
+        })
+    }
+
+    fn tracks_json(&self) -> String {
+        serde_json::to_string(&self.tracks).unwrap_or_else(|_| "{}".to_string())
+    }
+}
+
+/// Operations that delete checkpoints or objects, or rewrite how they are
+/// stored. They are journaled so that undo stops at them instead of reaching
+/// back to states that may depend on what they removed.
+pub const IRREVERSIBLE_OPERATIONS: &[&str] = &["prune", "gc", "migrate", "fsck --repair"];
+
+/// A row of the `operations` table
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub id: i64,
+    pub op: String,
+    pub timestamp: i64,
+    pub argv: String,
+    pub before: RepoState,
+    pub after: RepoState,
+    /// Whether the operation rewrote the working tree to match HEAD
+    pub touches_workspace: bool,
+    pub undone: bool,
+}
+
+impl Operation {
+    pub fn is_irreversible(&self) -> bool {
+        IRREVERSIBLE_OPERATIONS.contains(&self.op.as_str())
+    }
+}
+
+/// Captures the repository state when a command starts so the change can be
+/// journaled once it finishes
+pub struct OperationTracker {
+    op: &'static str,
+    touches_workspace: bool,
+    before: RepoState,
+    last_op_id: i64,
+}
+
+impl OperationTracker {
+    pub fn begin(
+        root: &Path,
+        conn: &Connection,
+        op: &'static str,
+        touches_workspace: bool,
+    ) -> Result<Self> {
+        Ok(OperationTracker {
+            op,
+            touches_workspace,
+            before: RepoState::capture(root, conn)?,
+            last_op_id: last_operation_id(conn)?,
+        })
+    }
+
+    pub fn finish(self, root: &Path, conn: &Connection) -> Result<()> {
+        // Operations journaled while this one ran (e.g. a rescue checkpoint)
+        // already cover the earlier part of the change
+        let before = match last_operation_id(conn)? {
+            id if id > self.last_op_id => load_operation(conn, id)?.after,
+            _ => self.before,
+        };
+        record_operation(root, conn, self.op, &before, self.touches_workspace)
+    }
+}
+
+fn last_operation_id(conn: &Connection) -> Result<i64> {
+    Ok(
+        conn.query_row("SELECT COALESCE(MAX(id), 0) FROM operations", [], |row| {
+            row.get(0)
+        })?,
+    )
+}
+
+/// Journal a completed operation. Recording a new operation discards anything
+/// that was undone and could otherwise have been redone.
+pub fn record_operation(
+    root: &Path,
+    conn: &Connection,
+    op: &str,
+    before: &RepoState,
+    touches_workspace: bool,
+) -> Result<()> {
+    let after = RepoState::capture(root, conn)?;
+    if after == *before {
+        return Ok(());
+    }
+    insert_operation(conn, op, before, &after, touches_workspace)
+}
+
+/// Journal one of the `IRREVERSIBLE_OPERATIONS`, even though it left HEAD and
+/// the track heads where they were, so that undo refuses to cross it
+pub fn record_irreversible_operation(root: &Path, conn: &Connection, op: &str) -> Result<()> {
+    let state = RepoState::capture(root, conn)?;
+    insert_operation(conn, op, &state, &state, false)
+}
+
+fn insert_operation(
+    conn: &Connection,
+    op: &str,
+    before: &RepoState,
+    after: &RepoState,
+    touches_workspace: bool,
+) -> Result<()> {
+    let argv: Vec<String> = std::iter::once("vibesnap".to_string())
+        .chain(std::env::args().skip(1))
+        .collect();
+    conn.execute("DELETE FROM operations WHERE undone = 1", [])?;
+    conn.execute(
+        "INSERT INTO operations(op, timestamp, argv, head_before, head_after, tracks_before, tracks_after, touches_workspace)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![
+            op,
+            chrono::Utc::now().timestamp(),
+            argv.join(" "),
+            before.head_string(),
+            after.head_string(),
+            before.tracks_json(),
+            after.tracks_json(),
+            touches_workspace as i64
+        ],
+    )?;
+    Ok(())
+}
+
+const OPERATION_COLUMNS: &str = "id, op, timestamp, argv, head_before, head_after, tracks_before, tracks_after, touches_workspace, undone";
+
+fn operation_from_row(row: &rusqlite::Row) -> rusqlite::Result<Operation> {
+    let state = |head_idx: usize, tracks_idx: usize| -> rusqlite::Result<RepoState> {
+        let head: String = row.get(head_idx)?;
+        let tracks: String = row.get(tracks_idx)?;
+        RepoState::from_columns(&head, &tracks).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(
+                tracks_idx,
+                rusqlite::types::Type::Text,
+                Box::new(e),
+            )
+        })
+    };
+    Ok(Operation {
+        id: row.get(0)?,
+        op: row.get(1)?,
+        timestamp: row.get(2)?,
+        argv: row.get(3)?,
+        before: state(4, 6)?,
+        after: state(5, 7)?,
+        touches_workspace: row.get::<_, i64>(8)? == 1,
+        undone: row.get::<_, i64>(9)? == 1,
+    })
+}
+
+fn load_operation(conn: &Connection, id: i64) -> Result<Operation> {
+    Ok(conn.query_row(
+        &format!("SELECT {} FROM operations WHERE id = ?", OPERATION_COLUMNS),
+        rusqlite::params![id],
+        operation_from_row,
+    )?)
+}
+
+/// Most recent operations first
+pub fn list_operations(conn: &Connection, limit: usize) -> Result<Vec<Operation>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM operations ORDER BY id DESC LIMIT ?",
+        OPERATION_COLUMNS
+    ))?;
+    let operations = stmt
+        .query_map(rusqlite::params![limit as i64], operation_from_row)?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(operations)
+}
+
+/// The operation `undo` would revert: the newest one not already undone
+pub fn last_active_operation(conn: &Connection) -> Result<Option<Operation>> {
+    find_operation(
+        conn,
+        "SELECT {} FROM operations WHERE undone = 0 ORDER BY id DESC LIMIT 1",
+    )
+}
+
+/// The operation `redo` would reapply: the oldest undone one
+pub fn first_undone_operation(conn: &Connection) -> Result<Option<Operation>> {
+    find_operation(
+        conn,
+        "SELECT {} FROM operations WHERE undone = 1 ORDER BY id ASC LIMIT 1",
+    )
+}
+
+fn find_operation(conn: &Connection, query: &str) -> Result<Option<Operation>> {
+    Ok(conn
+        .query_row(
+            &query.replace("{}", OPERATION_COLUMNS),
+            [],
+            operation_from_row,
+        )
+        .optional()?)
+}
+
+pub fn set_undone(conn: &Connection, id: i64, undone: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE operations SET undone = ? WHERE id = ?",
+        rusqlite::params![undone as i64, id],
+    )?;
+    Ok(())
+}
+
+/// Check that every checkpoint `state` points at still exists
+pub fn validate_state(conn: &Connection, state: &RepoState) -> Result<()> {
+    // A head recorded before a prune may name a checkpoint that is gone, and
+    // so may HEAD itself (e.g. a detached checkout of a pruned auto-snap)
+    for head in state
+        .tracks
+        .values()
+        .flatten()
+        .chain(state.checkpoint_id.as_ref())
+    {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM checkpoints WHERE id = ?)",
+            [head],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(AppError::Generic(format!(
+                "Cannot return to that state: checkpoint {} no longer exists (pruned?)",
+                head
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Point HEAD and every track head back at a recorded state. Tracks that did
+/// not exist in `state` are removed; the working tree is left untouched.
+pub fn apply_state(root: &Path, conn: &Connection, state: &RepoState) -> Result<()> {
+    validate_state(conn, state)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM tracks", [])?;
+    for (name, head) in &state.tracks {
+        tx.execute(
+            "INSERT INTO tracks(name, head) VALUES (?, ?)",
+            rusqlite::params![name, head],
+        )?;
+    }
+    tx.commit()?;
+    write_head(root, &state.track, state.checkpoint_id.as_deref())
+}
@@ -3,6 +3,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use super::constants::{DEFAULT_TRACK, HEAD_FILENAME, REPO_DIRNAME};
+use super::utils::write_atomic;
 
 pub fn find_repo_root(start: Option<PathBuf>) -> Result<PathBuf> {
     let current_dir: PathBuf =
@@ -30,7 +31,7 @@ pub fn read_head(root: &Path) -> Result<(String, Option<String>)> {
         return Ok((DEFAULT_TRACK.to_string(), None));
     }
     let content: String = fs::read_to_string(head_file)?;
-    let parts: Vec<&str> = content.trim().split_whitespace().collect();
+    let parts: Vec<&str> = content.split_whitespace().collect();
     match parts.as_slice() {
         [track] => Ok((track.to_string(), None)),
         [track, checkpoint_id] => Ok((track.to_string(), Some(checkpoint_id.to_string()))),
@@ -44,5 +45,5 @@ pub fn write_head(root: &Path, track: &str, checkpoint_id: Option<&str>) -> Resu
         Some(id) => format!("{} {}\n", track, id),
         None => format!("{}\n", track),
     };
-    fs::write(head_file, content).map_err(AppError::IoError)
+    write_atomic(&head_file, content.as_bytes()).map_err(AppError::IoError)
 }
@@ -0,0 +1,359 @@
+use crate::error::{AppError, Result};
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use super::checkpoint::CheckpointKind;
+use super::constants::{REPO_DIRNAME, SNAPSHOTS_DIRNAME};
+use super::repo::read_head;
+use super::utils::parse_duration;
+
+/// Keep every auto-snap for an hour, the newest per hour for a day and the
+/// newest per day for a month
+pub const DEFAULT_RETENTION_POLICY: &str = "1h:all,1d:1h,30d:1d";
+
+/// Auto-snaps up to `max_age` seconds old keep one per `bucket` seconds, or
+/// all of them when `bucket` is `None`
+#[derive(Debug, Clone, Copy)]
+struct RetentionTier {
+    max_age: i64,
+    bucket: Option<i64>,
+}
+
+/// Which auto-snaps survive as they age. Auto-snaps older than the last tier
+/// are dropped.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    tiers: Vec<RetentionTier>,
+}
+
+impl RetentionPolicy {
+    /// Parse a comma-separated list of `<age>:<interval>` tiers, where
+    /// `<interval>` is a duration or `all`, e.g. `1h:all,1d:1h,30d:1d`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut tiers: Vec<RetentionTier> = Vec::new();
+        for tier in spec.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (age, interval) = tier.split_once(':').ok_or_else(|| {
+                AppError::Generic(format!(
+                    "Invalid retention tier '{}': expected <age>:<interval>",
+                    tier
+                ))
+            })?;
+            let max_age = parse_duration(age)?;
+            let bucket = match interval.trim() {
+                "all" => None,
+                interval => Some(parse_duration(interval)?),
+            };
+            if tiers.last().is_some_and(|last| last.max_age >= max_age) {
+                return Err(AppError::Generic(format!(
+                    "Retention tiers must be in increasing order of age: {}",
+                    spec
+                )));
+            }
+            tiers.push(RetentionTier { max_age, bucket });
+        }
+        if tiers.is_empty() {
+            return Err(AppError::Generic(
+                "Retention policy has no tiers".to_string(),
+            ));
+        }
+        Ok(RetentionPolicy { tiers })
+    }
+}
+
+/// A checkpoint the retention policy would delete
+#[derive(Debug, Clone)]
+pub struct PrunedCheckpoint {
+    pub id: String,
+    pub track: String,
+    pub timestamp: i64,
+    pub note: Option<String>,
+}
+
+/// Work out which watcher auto-snaps `policy` thins out as of `now`. Manual
+/// snaps, rescue checkpoints, track heads and the checkpoint HEAD points at
+/// are always kept. Within a
+/// tier, the newest auto-snap of each track in each interval survives.
+pub fn plan_prune(
+    root: &Path,
+    conn: &Connection,
+    policy: &RetentionPolicy,
+    now: i64,
+) -> Result<Vec<PrunedCheckpoint>> {
+    let mut protected: HashSet<String> = conn
+        .prepare("SELECT head FROM tracks WHERE head IS NOT NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<std::result::Result<_, _>>()?;
+    if let (_, Some(head)) = read_head(root)? {
+        protected.insert(head);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT id, track, timestamp, note FROM checkpoints WHERE kind = ? ORDER BY timestamp DESC",
+    )?;
+    let auto_snaps = stmt
+        .query_map([CheckpointKind::Auto.as_str()], |row| {
+            Ok(PrunedCheckpoint {
+                id: row.get(0)?,
+                track: row.get(1)?,
+                timestamp: row.get(2)?,
+                note: row.get(3)?,
+            })
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let mut occupied: HashSet<(String, usize, i64)> = HashSet::new();
+    let mut pruned = Vec::new();
+    for checkpoint in auto_snaps {
+        let age = now - checkpoint.timestamp;
+        let tier = policy
+            .tiers
+            .iter()
+            .enumerate()
+            .find(|(_, tier)| age <= tier.max_age);
+        // Newest first, so the first auto-snap to claim an interval keeps it
+        let keep = match tier {
+            Some((_, RetentionTier { bucket: None, .. })) => true,
+            Some((
+                index,
+                RetentionTier {
+                    bucket: Some(bucket),
+                    ..
+                },
+            )) => occupied.insert((
+                checkpoint.track.clone(),
+                index,
+                checkpoint.timestamp.div_euclid(*bucket),
+            )),
+            None => false,
+        };
+        if !keep && !protected.contains(&checkpoint.id) {
+            pruned.push(checkpoint);
+        }
+    }
+    Ok(pruned)
+}
+
+/// Delete checkpoints chosen by `plan_prune`, pointing the children of each
+/// at its nearest surviving ancestor so the history stays connected. Objects
+/// are left for `gc` to collect.
+pub fn apply_prune(root: &Path, conn: &mut Connection, pruned: &[PrunedCheckpoint]) -> Result<()> {
+    if pruned.is_empty() {
+        return Ok(());
+    }
+
+    let parents: HashMap<String, Option<String>> = conn
+        .prepare("SELECT id, parent FROM checkpoints")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    let doomed: HashSet<&str> = pruned.iter().map(|c| c.id.as_str()).collect();
+
+    let surviving_ancestor = |mut parent: Option<String>| {
+        while let Some(id) = parent.as_deref()
+            && doomed.contains(id)
+        {
+            parent = parents.get(id).cloned().flatten();
+        }
+        parent
+    };
+
+    let tx = conn.transaction()?;
+    for (id, parent) in &parents {
+        if doomed.contains(id.as_str()) {
+            continue;
+        }
+        if let Some(parent_id) = parent
+            && doomed.contains(parent_id.as_str())
+        {
+            tx.execute(
+                "UPDATE checkpoints SET parent = ? WHERE id = ?",
+                rusqlite::params![surviving_ancestor(parent.clone()), id],
+            )?;
+        }
+    }
+    for checkpoint in pruned {
+        tx.execute(
+            "DELETE FROM checkpoints WHERE id = ?",
+            rusqlite::params![checkpoint.id],
+        )?;
+    }
+    tx.commit()?;
+
+    let snapshots_dir = root.join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME);
+    for checkpoint in pruned {
+        let manifest_path = snapshots_dir.join(format!("{}.json", checkpoint.id));
+        if manifest_path.exists() {
+            fs::remove_file(manifest_path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::checkpoint::CheckpointKind::{Auto, Manual, Rescue};
+    use crate::vibe::db::db_connect;
+    use crate::vibe::repo::write_head;
+
+    const NOW: i64 = 1_000_000 * 3600;
+
+    /// A repo whose checkpoints, oldest first, are `(id, age, kind)`, each the
+    /// parent of the next, on track `main` with its head at `head`
+    fn repo(
+        checkpoints: &[(&str, i64, CheckpointKind)],
+        head: &str,
+    ) -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME)).unwrap();
+        let conn = db_connect(dir.path()).unwrap();
+        let mut parent: Option<&str> = None;
+        for (id, age, kind) in checkpoints {
+            conn.execute(
+                "INSERT INTO checkpoints(id, track, parent, timestamp, kind) VALUES (?, 'main', ?, ?, ?)",
+                rusqlite::params![id, parent, NOW - age, kind.as_str()],
+            )
+            .unwrap();
+            parent = Some(id);
+        }
+        conn.execute("INSERT INTO tracks(name, head) VALUES ('main', ?)", [head])
+            .unwrap();
+        write_head(dir.path(), "main", Some(head)).unwrap();
+        (dir, conn)
+    }
+
+    fn pruned_ids(root: &Path, conn: &Connection, policy: &str) -> Vec<String> {
+        let policy = RetentionPolicy::parse(policy).unwrap();
+        let mut ids: Vec<String> = plan_prune(root, conn, &policy, NOW)
+            .unwrap()
+            .into_iter()
+            .map(|checkpoint| checkpoint.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn parse_tiers() {
+        let policy = RetentionPolicy::parse(DEFAULT_RETENTION_POLICY).unwrap();
+        let tiers: Vec<(i64, Option<i64>)> = policy
+            .tiers
+            .iter()
+            .map(|tier| (tier.max_age, tier.bucket))
+            .collect();
+        assert_eq!(
+            tiers,
+            vec![(3600, None), (86400, Some(3600)), (30 * 86400, Some(86400))]
+        );
+        assert_eq!(
+            RetentionPolicy::parse(" 2h : all , ").unwrap().tiers.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_policies() {
+        for spec in [
+            "",
+            ",",
+            "1h",
+            "1d:1h,1h:all",
+            "1h:all,1h:all",
+            "1x:all",
+            "1h:often",
+        ] {
+            assert!(RetentionPolicy::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn plan_keeps_newest_per_interval_and_protected_checkpoints() {
+        let (dir, conn) = repo(
+            &[
+                ("expired", 2 * 86400 + 10, Auto),
+                ("manual", 2 * 86400 + 5, Manual),
+                ("older-in-hour", 2 * 3600 + 600, Auto),
+                ("newer-in-hour", 2 * 3600 + 300, Auto),
+                ("recent-1", 1200, Auto),
+                ("recent-2", 600, Auto),
+                ("head", 2 * 86400, Auto),
+            ],
+            "head",
+        );
+        assert_eq!(
+            pruned_ids(dir.path(), &conn, "1h:all,1d:1h"),
+            vec!["expired", "older-in-hour"]
+        );
+    }
+
+    #[test]
+    fn plan_never_prunes_the_checkpoint_head_points_at() {
+        let (dir, conn) = repo(&[("old", 2 * 86400, Auto), ("tip", 0, Manual)], "tip");
+        write_head(dir.path(), "main", Some("old")).unwrap();
+        assert!(pruned_ids(dir.path(), &conn, "1h:all").is_empty());
+    }
+
+    #[test]
+    fn apply_reattaches_children_to_surviving_ancestor() {
+        let (dir, mut conn) = repo(
+            &[
+                ("a", 400, Manual),
+                ("b", 300, Auto),
+                ("c", 200, Auto),
+                ("d", 100, Manual),
+            ],
+            "d",
+        );
+        let manifest = dir
+            .path()
+            .join(REPO_DIRNAME)
+            .join(SNAPSHOTS_DIRNAME)
+            .join("b.json");
+        fs::write(&manifest, "{}").unwrap();
+        let pruned = plan_prune(
+            dir.path(),
+            &conn,
+            &RetentionPolicy::parse("1s:all").unwrap(),
+            NOW,
+        )
+        .unwrap();
+        apply_prune(dir.path(), &mut conn, &pruned).unwrap();
+
+        let parent: Option<String> = conn
+            .query_row("SELECT parent FROM checkpoints WHERE id = 'd'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(parent.as_deref(), Some("a"));
+        let remaining: i64 = conn
+            .query_row("SELECT COUNT(*) FROM checkpoints", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 2);
+        assert!(!manifest.exists());
+    }
+
+    #[test]
+    fn rescue_checkpoints_outlive_every_tier() {
+        let (dir, conn) = repo(
+            &[
+                ("rescue", 40 * 86400, Rescue),
+                ("expired", 40 * 86400 - 10, Auto),
+                ("tip", 0, Manual),
+            ],
+            "tip",
+        );
+        assert_eq!(
+            pruned_ids(dir.path(), &conn, DEFAULT_RETENTION_POLICY),
+            vec!["expired"]
+        );
+    }
+
+    #[test]
+    fn plan_selects_by_kind_not_is_auto() {
+        let (dir, conn) = repo(&[("flagged", 2 * 86400, Manual), ("tip", 0, Manual)], "tip");
+        conn.execute("UPDATE checkpoints SET is_auto = 1", [])
+            .unwrap();
+        assert!(pruned_ids(dir.path(), &conn, "1h:all").is_empty());
+    }
+}
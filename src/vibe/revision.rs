@@ -0,0 +1,452 @@
+use crate::error::{AppError, Result};
+use rusqlite::{Connection, OptionalExtension};
+use std::path::Path;
+
+use super::oplog::list_operations;
+use super::repo::read_head;
+use super::utils::parse_duration;
+
+/// Most candidates listed in an ambiguity error
+const MAX_CANDIDATES_SHOWN: usize = 10;
+
+/// A checkpoint matching a prefix: its ID, track and note
+type Candidate = (String, String, Option<String>);
+
+/// Resolve a full checkpoint ID or any unique prefix of one (case
+/// insensitive) to the full ID
+pub fn resolve_checkpoint_id(conn: &Connection, input: &str) -> Result<String> {
+    let prefix = input.trim().to_uppercase();
+    if prefix.is_empty() || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(AppError::CheckpointNotFound(input.to_string()));
+    }
+
+    let exact: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM checkpoints WHERE id = ?)",
+        [&prefix],
+        |row| row.get(0),
+    )?;
+    if exact {
+        return Ok(prefix);
+    }
+
+    let candidates = checkpoints_with_prefix(conn, input)?;
+    match candidates.len() {
+        0 => Err(AppError::CheckpointNotFound(input.to_string())),
+        1 => Ok(candidates[0].0.clone()),
+        _ => Err(ambiguous(input, Vec::new(), &candidates)),
+    }
+}
+
+/// Checkpoints whose ID starts with `input` (case insensitive), newest
+/// first. Nothing matches input that is not hex.
+fn checkpoints_with_prefix(conn: &Connection, input: &str) -> Result<Vec<Candidate>> {
+    let prefix = input.trim().to_uppercase();
+    if prefix.is_empty() || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(Vec::new());
+    }
+    // Hex digits contain no LIKE wildcards, so the prefix can be used as-is
+    let mut stmt = conn.prepare(
+        "SELECT id, track, note FROM checkpoints WHERE id LIKE ? || '%' ORDER BY timestamp DESC",
+    )?;
+    let candidates = stmt
+        .query_map([&prefix], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<std::result::Result<_, _>>()?;
+    Ok(candidates)
+}
+
+/// The error for `input` matching more than one thing: the `others` lines
+/// followed by the first few `candidates`
+fn ambiguous(input: &str, mut others: Vec<String>, candidates: &[Candidate]) -> AppError {
+    others.extend(
+        candidates
+            .iter()
+            .take(MAX_CANDIDATES_SHOWN)
+            .map(|(id, track, note)| {
+                format!(
+                    "  {} [{}] {}",
+                    id,
+                    track,
+                    note.as_deref().unwrap_or_default()
+                )
+            }),
+    );
+    if candidates.len() > MAX_CANDIDATES_SHOWN {
+        others.push(format!(
+            "  ... and {} more",
+            candidates.len() - MAX_CANDIDATES_SHOWN
+        ));
+    }
+    AppError::AmbiguousCheckpoint(input.to_string(), others.join("\n"))
+}
+
+/// Resolve a revision to a full checkpoint ID. A revision is a base followed
+/// by any number of `~N` (N parents up, default 1) and `^` (one parent up)
+/// suffixes. The base is one of:
+///
+/// - `HEAD`: the current checkpoint
+/// - `<track>`: the head of that track
+/// - `<track>@{<duration> ago}` or `HEAD@{<duration> ago}`: the newest
+///   checkpoint on that track (or the current one) at least that old
+/// - `@{-N}`: where HEAD was N moves ago, according to the operation log
+/// - a checkpoint ID or any unique prefix of one
+pub fn resolve_revision(root: &Path, conn: &Connection, revision: &str) -> Result<String> {
+    let revision = revision.trim();
+    let invalid =
+        |reason: &str| AppError::InvalidRevision(revision.to_string(), reason.to_string());
+
+    // Suffixes start after any @{...} block
+    let search_from = match (revision.find("@{"), revision.find('}')) {
+        (Some(_), Some(close)) => close + 1,
+        _ => 0,
+    };
+    let suffix_start = revision[search_from..]
+        .find(['~', '^'])
+        .map_or(revision.len(), |pos| search_from + pos);
+    let (base, suffix) = revision.split_at(suffix_start);
+
+    let mut checkpoint_id = resolve_base(root, conn, base).map_err(|e| match e {
+        AppError::Generic(reason) => invalid(&reason),
+        e => e,
+    })?;
+
+    let mut chars = suffix.chars().peekable();
+    while let Some(op) = chars.next() {
+        let steps = match op {
+            '^' => 1,
+            '~' => {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                }
+                if digits.is_empty() {
+                    1
+                } else {
+                    digits
+                        .parse::<usize>()
+                        .map_err(|_| invalid("bad ~ count"))?
+                }
+            }
+            _ => return Err(invalid("expected ~N or ^ after the base")),
+        };
+        for _ in 0..steps {
+            checkpoint_id = parent_of(conn, &checkpoint_id)?
+                .ok_or_else(|| invalid(&format!("{} has no parent", checkpoint_id)))?;
+        }
+    }
+
+    Ok(checkpoint_id)
+}
+
+fn resolve_base(root: &Path, conn: &Connection, base: &str) -> Result<String> {
+    if base.is_empty() {
+        return Err(AppError::Generic("empty revision".to_string()));
+    }
+
+    // @{-N}: previous positions of HEAD
+    if let Some(n) = base
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        let n: usize = n
+            .parse()
+            .map_err(|_| AppError::Generic(format!("bad position number '{}'", n)))?;
+        return previous_position(root, conn, n);
+    }
+
+    // <track>@{<duration> ago}
+    if let Some((name, spec)) = base.split_once("@{") {
+        let spec = spec
+            .strip_suffix('}')
+            .ok_or_else(|| AppError::Generic("missing closing '}'".to_string()))?;
+        let duration = spec
+            .trim()
+            .strip_suffix("ago")
+            .ok_or_else(|| AppError::Generic("expected '@{<duration> ago}'".to_string()))?;
+        let seconds = parse_duration(duration)?;
+        let track = match name {
+            "" | "HEAD" => read_head(root)?.0,
+            name => name.to_string(),
+        };
+        let cutoff = chrono::Utc::now().timestamp() - seconds;
+        return conn
+            .query_row(
+                "SELECT id FROM checkpoints WHERE track = ? AND timestamp <= ? ORDER BY timestamp DESC, rowid DESC LIMIT 1",
+                rusqlite::params![track, cutoff],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| {
+                AppError::Generic(format!(
+                    "track {} has no checkpoint that old",
+                    track
+                ))
+            });
+    }
+
+    if base == "HEAD" {
+        return read_head(root)?
+            .1
+            .ok_or_else(|| AppError::Generic("HEAD has no checkpoint yet".to_string()));
+    }
+
+    let track_head: Option<Option<String>> = conn
+        .query_row("SELECT head FROM tracks WHERE name = ?", [base], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    let Some(track_head) = track_head else {
+        return resolve_checkpoint_id(conn, base);
+    };
+    // A track named like a hex prefix must not silently hide checkpoints
+    let shadowed = checkpoints_with_prefix(conn, base)?;
+    if !shadowed.is_empty() {
+        return Err(ambiguous(
+            base,
+            vec![format!("  track {}", base)],
+            &shadowed,
+        ));
+    }
+    track_head.ok_or_else(|| AppError::Generic(format!("track {} has no checkpoints yet", base)))
+}
+
+/// Where HEAD pointed `n` moves ago (0 is the current checkpoint), following
+/// the operations that are still in effect
+fn previous_position(root: &Path, conn: &Connection, n: usize) -> Result<String> {
+    let mut positions: Vec<Option<String>> = vec![read_head(root)?.1];
+    for operation in list_operations(conn, i64::MAX as usize)? {
+        if operation.undone || operation.before.checkpoint_id == operation.after.checkpoint_id {
+            continue;
+        }
+        if positions.last() != Some(&operation.before.checkpoint_id) {
+            positions.push(operation.before.checkpoint_id);
+        }
+    }
+    match positions.get(n) {
+        Some(Some(id)) => Ok(id.clone()),
+        Some(None) => Err(AppError::Generic(format!(
+            "HEAD had no checkpoint {} moves ago",
+            n
+        ))),
+        None => Err(AppError::Generic(format!(
+            "the operation log only goes back {} moves",
+            positions.len() - 1
+        ))),
+    }
+}
+
+fn parent_of(conn: &Connection, checkpoint_id: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT parent FROM checkpoints WHERE id = ?",
+        [checkpoint_id],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| AppError::CheckpointNotFound(checkpoint_id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::constants::REPO_DIRNAME;
+    use crate::vibe::db::db_connect;
+    use crate::vibe::oplog::{RepoState, record_operation};
+    use crate::vibe::repo::write_head;
+
+    const ROOT: &str = "AAAA000000000001";
+    const MIDDLE: &str = "AAAA000000000002";
+    const TIP: &str = "BBBB000000000003";
+    const OTHER: &str = "CCCC000000000004";
+
+    /// `ROOT <- MIDDLE <- TIP` on `main`, made 3h, 2h and 1h ago, and
+    /// `OTHER` forked from `ROOT` on `feature`, with HEAD at `TIP`
+    fn repo() -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(dir.path()).unwrap();
+        let now = chrono::Utc::now().timestamp();
+        for (id, track, parent, hours_ago) in [
+            (ROOT, "main", None, 3),
+            (MIDDLE, "main", Some(ROOT), 2),
+            (TIP, "main", Some(MIDDLE), 1),
+            (OTHER, "feature", Some(ROOT), 1),
+        ] {
+            conn.execute(
+                "INSERT INTO checkpoints(id, track, parent, timestamp) VALUES (?, ?, ?, ?)",
+                rusqlite::params![id, track, parent, now - hours_ago * 3600],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO tracks(name, head) VALUES ('main', ?), ('feature', ?), ('empty', NULL)",
+            [TIP, OTHER],
+        )
+        .unwrap();
+        write_head(dir.path(), "main", Some(TIP)).unwrap();
+        (dir, conn)
+    }
+
+    #[test]
+    fn head_tracks_and_ids() {
+        let (dir, conn) = repo();
+        let root = dir.path();
+        assert_eq!(resolve_revision(root, &conn, "HEAD").unwrap(), TIP);
+        assert_eq!(resolve_revision(root, &conn, " feature ").unwrap(), OTHER);
+        assert_eq!(resolve_revision(root, &conn, MIDDLE).unwrap(), MIDDLE);
+        assert_eq!(resolve_revision(root, &conn, "bbbb").unwrap(), TIP);
+        assert!(matches!(
+            resolve_revision(root, &conn, "AAAA"),
+            Err(AppError::AmbiguousCheckpoint(..))
+        ));
+        assert!(matches!(
+            resolve_revision(root, &conn, "DDDD"),
+            Err(AppError::CheckpointNotFound(_))
+        ));
+        assert!(matches!(
+            resolve_revision(root, &conn, "empty"),
+            Err(AppError::InvalidRevision(..))
+        ));
+    }
+
+    #[test]
+    fn parent_suffixes() {
+        let (dir, conn) = repo();
+        let root = dir.path();
+        assert_eq!(resolve_revision(root, &conn, "HEAD^").unwrap(), MIDDLE);
+        assert_eq!(resolve_revision(root, &conn, "HEAD~").unwrap(), MIDDLE);
+        assert_eq!(resolve_revision(root, &conn, "HEAD~2").unwrap(), ROOT);
+        assert_eq!(resolve_revision(root, &conn, "main^~1").unwrap(), ROOT);
+        assert_eq!(resolve_revision(root, &conn, "feature^").unwrap(), ROOT);
+        assert!(matches!(
+            resolve_revision(root, &conn, "HEAD~3"),
+            Err(AppError::InvalidRevision(..))
+        ));
+        assert!(matches!(
+            resolve_revision(root, &conn, "HEAD~x"),
+            Err(AppError::InvalidRevision(..))
+        ));
+    }
+
+    #[test]
+    fn time_based_revisions() {
+        let (dir, conn) = repo();
+        let root = dir.path();
+        assert_eq!(
+            resolve_revision(root, &conn, "main@{90m ago}").unwrap(),
+            MIDDLE
+        );
+        assert_eq!(
+            resolve_revision(root, &conn, "HEAD@{2h30m ago}").unwrap(),
+            ROOT
+        );
+        assert_eq!(resolve_revision(root, &conn, "@{1m ago}").unwrap(), TIP);
+        assert_eq!(
+            resolve_revision(root, &conn, "main@{90m ago}^").unwrap(),
+            ROOT
+        );
+        for revision in ["main@{5h ago}", "main@{1h", "main@{1h}"] {
+            assert!(
+                matches!(
+                    resolve_revision(root, &conn, revision),
+                    Err(AppError::InvalidRevision(..))
+                ),
+                "{}",
+                revision
+            );
+        }
+    }
+
+    #[test]
+    fn previous_positions_follow_the_operation_log() {
+        let (dir, conn) = repo();
+        let root = dir.path();
+        for target in [ROOT, OTHER] {
+            let before = RepoState::capture(root, &conn).unwrap();
+            write_head(root, "main", Some(target)).unwrap();
+            record_operation(root, &conn, "restore", &before, true).unwrap();
+        }
+        assert_eq!(resolve_revision(root, &conn, "@{-0}").unwrap(), OTHER);
+        assert_eq!(resolve_revision(root, &conn, "@{-1}").unwrap(), ROOT);
+        assert_eq!(resolve_revision(root, &conn, "@{-2}").unwrap(), TIP);
+        assert_eq!(resolve_revision(root, &conn, "@{-1}~0").unwrap(), ROOT);
+        assert!(matches!(
+            resolve_revision(root, &conn, "@{-3}"),
+            Err(AppError::InvalidRevision(..))
+        ));
+    }
+
+    #[test]
+    fn hex_track_names_that_shadow_a_prefix_are_ambiguous() {
+        let (dir, conn) = repo();
+        let root = dir.path();
+        conn.execute(
+            "INSERT INTO tracks(name, head) VALUES ('cccc', ?1), ('beef', ?1)",
+            [ROOT],
+        )
+        .unwrap();
+
+        match resolve_revision(root, &conn, "cccc~0") {
+            Err(AppError::AmbiguousCheckpoint(input, listing)) => {
+                assert_eq!(input, "cccc");
+                assert!(listing.contains("track cccc") && listing.contains(OTHER));
+            }
+            other => panic!("expected ambiguity, got {:?}", other),
+        }
+        // No checkpoint starts with beef, so the track is all it can mean
+        assert_eq!(resolve_revision(root, &conn, "beef").unwrap(), ROOT);
+    }
+
+    fn repo_with_ids(ids: &[&str]) -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(REPO_DIRNAME)).unwrap();
+        let conn = db_connect(dir.path()).unwrap();
+        for (age, id) in ids.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO checkpoints(id, track, timestamp, note) VALUES (?, 'main', ?, 'n')",
+                rusqlite::params![id, -(age as i64)],
+            )
+            .unwrap();
+        }
+        (dir, conn)
+    }
+
+    #[test]
+    fn unique_prefixes_resolve_case_insensitively() {
+        let (_dir, conn) = repo_with_ids(&["AB12000000000001", "CD34000000000002"]);
+        assert_eq!(
+            resolve_checkpoint_id(&conn, "ab12").unwrap(),
+            "AB12000000000001"
+        );
+        assert_eq!(
+            resolve_checkpoint_id(&conn, " cd34000000000002 ").unwrap(),
+            "CD34000000000002"
+        );
+        for input in ["EF", "", "not-hex", "AB1%"] {
+            assert!(
+                matches!(
+                    resolve_checkpoint_id(&conn, input),
+                    Err(AppError::CheckpointNotFound(_))
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn shared_prefixes_are_ambiguous() {
+        let ids: Vec<String> = (0..12).map(|n| format!("AB{:014X}", n)).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let (_dir, conn) = repo_with_ids(&ids);
+
+        match resolve_checkpoint_id(&conn, "ab") {
+            Err(AppError::AmbiguousCheckpoint(input, listing)) => {
+                assert_eq!(input, "ab");
+                assert_eq!(listing.lines().count(), MAX_CANDIDATES_SHOWN + 1);
+                assert!(listing.ends_with("... and 2 more"));
+            }
+            other => panic!("expected ambiguity, got {:?}", other),
+        }
+        // A full ID resolves to itself
+        assert_eq!(resolve_checkpoint_id(&conn, ids[3]).unwrap(), ids[3]);
+    }
+}
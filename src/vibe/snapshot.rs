@@ -1,18 +1,42 @@
+use crate::config::Config;
 use crate::error::{AppError, Result};
 use colored::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use walkdir;
 
 use super::constants::{REPO_DIRNAME, SNAPSHOTS_DIRNAME};
-use super::ignore::{read_ignore_patterns, should_ignore_path};
-use super::objects::store_content_in_objects;
+use super::entry::{FileEntry, read_entry_content};
+use super::ignore::{read_ignore_rules, should_ignore_path};
+use super::index::StatCache;
+use super::objects::{object_exists, store_content_in_objects};
+use super::tree::{load_tree, write_tree};
+use super::utils::{canonicalize_keeping_link, parallel_map, resolve_jobs, write_atomic};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct SnapshotManifest {
-    pub files: HashMap<String, String>, // path -> content hash
+    pub files: BTreeMap<String, FileEntry>, // path -> entry, sorted so manifests are stable
+    /// Directories with nothing recorded below them, so restores recreate
+    /// them; directories holding files are implied by the file paths
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub dirs: BTreeSet<String>,
+}
+
+/// What a manifest file under `.vibe/snapshots` holds: the root tree of the
+/// checkpoint, or the whole manifest as written before trees existed
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredManifest {
+    Tree { tree: String },
+    Full(SnapshotManifest),
+}
+
+fn manifest_file_path(root: &Path, checkpoint_id: &str) -> PathBuf {
+    root.join(REPO_DIRNAME)
+        .join(SNAPSHOTS_DIRNAME)
+        .join(format!("{}.json", checkpoint_id))
 }
 
 // Helper function to save snapshot manifest
@@ -24,19 +48,22 @@ pub fn save_snapshot_manifest(
     let snapshots_dir: PathBuf = root.join(REPO_DIRNAME).join(SNAPSHOTS_DIRNAME);
     fs::create_dir_all(&snapshots_dir)?;
 
-    let manifest_path: PathBuf = snapshots_dir.join(format!("{}.json", checkpoint_id));
-    let json_content: String = serde_json::to_string_pretty(manifest)
+    // Trees are stored first so the manifest never names a missing one
+    let stored = StoredManifest::Tree {
+        tree: write_tree(root, manifest)?,
+    };
+    let json_content: String = serde_json::to_string_pretty(&stored)
         .map_err(|e| AppError::ManifestSerializationError(e.to_string()))?;
 
-    fs::write(manifest_path, json_content).map_err(AppError::IoError)
+    write_atomic(
+        &manifest_file_path(root, checkpoint_id),
+        json_content.as_bytes(),
+    )
+    .map_err(AppError::IoError)
 }
 
-// Helper function to load snapshot manifest
-pub fn load_snapshot_manifest(root: &Path, checkpoint_id: &str) -> Result<SnapshotManifest> {
-    let manifest_path: PathBuf = root
-        .join(REPO_DIRNAME)
-        .join(SNAPSHOTS_DIRNAME)
-        .join(format!("{}.json", checkpoint_id));
+fn load_stored_manifest(root: &Path, checkpoint_id: &str) -> Result<StoredManifest> {
+    let manifest_path = manifest_file_path(root, checkpoint_id);
 
     if !manifest_path.exists() {
         return Err(AppError::ManifestNotFound(checkpoint_id.to_string()));
@@ -47,17 +74,139 @@ pub fn load_snapshot_manifest(root: &Path, checkpoint_id: &str) -> Result<Snapsh
         .map_err(|e| AppError::ManifestDeserializationError(e.to_string()))
 }
 
+// Helper function to load snapshot manifest
+pub fn load_snapshot_manifest(root: &Path, checkpoint_id: &str) -> Result<SnapshotManifest> {
+    match load_stored_manifest(root, checkpoint_id)? {
+        StoredManifest::Tree { tree } => load_tree(root, &tree),
+        StoredManifest::Full(manifest) => Ok(manifest),
+    }
+}
+
+/// The root tree of a checkpoint, or `None` for a manifest written before
+/// trees existed
+pub fn load_snapshot_tree(root: &Path, checkpoint_id: &str) -> Result<Option<String>> {
+    match load_stored_manifest(root, checkpoint_id)? {
+        StoredManifest::Tree { tree } => Ok(Some(tree)),
+        StoredManifest::Full(_) => Ok(None),
+    }
+}
+
+/// Rewrite a manifest from before trees existed as a root tree. Returns
+/// whether anything was rewritten.
+pub fn upgrade_snapshot_manifest(root: &Path, checkpoint_id: &str) -> Result<bool> {
+    match load_stored_manifest(root, checkpoint_id)? {
+        StoredManifest::Tree { .. } => Ok(false),
+        StoredManifest::Full(manifest) => {
+            save_snapshot_manifest(root, checkpoint_id, &manifest)?;
+            Ok(true)
+        }
+    }
+}
+
+impl SnapshotManifest {
+    /// Replace everything at or below each of `prefixes` (manifest paths,
+    /// `""` meaning the whole tree) with the entries `changes` has there,
+    /// keeping the rest of this manifest as it was
+    pub fn overlay(&self, changes: SnapshotManifest, prefixes: &[String]) -> SnapshotManifest {
+        let covered = |path: &str| {
+            prefixes.iter().any(|prefix| {
+                prefix.is_empty()
+                    || path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        };
+        let mut files: BTreeMap<String, FileEntry> = self
+            .files
+            .iter()
+            .filter(|(path, _)| !covered(path))
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect();
+        files.extend(changes.files);
+        let mut dirs: BTreeSet<String> = self
+            .dirs
+            .iter()
+            .filter(|dir| !covered(dir))
+            .cloned()
+            .collect();
+        dirs.extend(changes.dirs);
+        let mut manifest = SnapshotManifest { files, dirs };
+        manifest.prune_implied_dirs();
+        manifest
+    }
+
+    /// Whether `dir` exists in this manifest, either recorded as an empty
+    /// directory or implied by something below it
+    pub fn has_dir(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        self.dirs.contains(dir)
+            || self
+                .files
+                .range(prefix.clone()..)
+                .next()
+                .is_some_and(|(path, _)| path.starts_with(&prefix))
+            || self
+                .dirs
+                .range(prefix.clone()..)
+                .next()
+                .is_some_and(|path| path.starts_with(&prefix))
+    }
+
+    /// Drop directory entries made redundant by a file or directory below them
+    pub fn prune_implied_dirs(&mut self) {
+        retain_leaf_dirs(&mut self.dirs, self.files.keys());
+    }
+}
+
+/// Remove from `dirs` every directory with one of `paths`, or another of
+/// `dirs`, below it
+pub fn retain_leaf_dirs<'a>(dirs: &mut BTreeSet<String>, paths: impl Iterator<Item = &'a String>) {
+    let mut implied: BTreeSet<String> = BTreeSet::new();
+    let mut add_ancestors = |path: &str| {
+        let mut rest = path;
+        while let Some((parent, _)) = rest.rsplit_once('/') {
+            if !implied.insert(parent.to_string()) {
+                break;
+            }
+            rest = parent;
+        }
+    };
+    paths.for_each(|path| add_ancestors(path));
+    dirs.iter().for_each(|dir| add_ancestors(dir));
+    dirs.retain(|dir| !implied.contains(dir));
+}
+
+/// Tuning for `build_snapshot_manifest`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuildOptions {
+    /// Ignore the stat cache and read and hash every file
+    pub rehash: bool,
+    /// Worker threads for reading, hashing and storing files; 0 falls back
+    /// to the `snapshot_jobs` config key
+    pub jobs: usize,
+}
+
+/// A file picked up by the walk, waiting to be stored
+struct PendingFile {
+    path: PathBuf,
+    manifest_path: String,
+    metadata: Option<fs::Metadata>,
+}
+
 // Helper function to build snapshot manifest from paths
 pub fn build_snapshot_manifest(
     root: &Path,
     paths_to_snap_relative_to_root_or_absolute: &[PathBuf],
+    options: BuildOptions,
 ) -> Result<SnapshotManifest> {
-    let mut manifest: SnapshotManifest = SnapshotManifest {
-        files: HashMap::new(),
-    };
+    let mut manifest = SnapshotManifest::default();
+    let mut cache = StatCache::load(root, options.rehash);
+    let mut covers_root = false;
+    let mut pending: Vec<PendingFile> = Vec::new();
 
-    // Read ignore patterns (prioritizing .gitignore over .vibeignore)
-    let ignore_patterns = read_ignore_patterns(root)?;
+    // Every ignore source, layered as `read_ignore_rules` describes
+    let ignore_rules = read_ignore_rules(root)?;
 
     for p_user_input in paths_to_snap_relative_to_root_or_absolute {
         let path_to_process: PathBuf = if p_user_input.is_absolute() {
@@ -66,7 +215,7 @@ pub fn build_snapshot_manifest(
             root.join(p_user_input)
         };
 
-        let source_path_canon: PathBuf = match path_to_process.canonicalize() {
+        let source_path_canon: PathBuf = match canonicalize_keeping_link(&path_to_process) {
             Ok(cp) => cp,
             Err(_e) => {
                 eprintln!(
@@ -81,7 +230,7 @@ pub fn build_snapshot_manifest(
             }
         };
 
-        if !source_path_canon.exists() {
+        let Ok(source_metadata) = fs::symlink_metadata(&source_path_canon) else {
             eprintln!(
                 "{}",
                 format!(
@@ -91,14 +240,14 @@ pub fn build_snapshot_manifest(
                 .yellow()
             );
             continue;
-        }
+        };
 
         // Check if this path should be ignored
-        if should_ignore_path(&source_path_canon, root, &ignore_patterns) {
+        if should_ignore_path(&source_path_canon, root, &ignore_rules) {
             eprintln!(
                 "{}",
                 format!(
-                    "Ignored: {} (matches .vibeignore pattern)",
+                    "Ignored: {} (matches an ignore rule; see `vibesnap check-ignore`)",
                     source_path_canon.display()
                 )
                 .yellow()
@@ -117,8 +266,16 @@ pub fn build_snapshot_manifest(
                 )
             });
 
-        if source_path_canon.is_dir() {
-            for entry_result in walkdir::WalkDir::new(&source_path_canon) {
+        if source_path_canon == root {
+            covers_root = true;
+        }
+
+        if source_metadata.is_dir() {
+            // Ignored directories, including .vibe and .git, are never entered
+            let walker = walkdir::WalkDir::new(&source_path_canon)
+                .into_iter()
+                .filter_entry(|entry| !should_ignore_path(entry.path(), root, &ignore_rules));
+            for entry_result in walker {
                 let entry: walkdir::DirEntry = match entry_result {
                     Ok(e) => e,
                     Err(e) => {
@@ -136,84 +293,279 @@ pub fn build_snapshot_manifest(
                 };
 
                 let entry_path: &Path = entry.path();
+                let rel_to_source_dir: &Path = entry_path.strip_prefix(&source_path_canon).unwrap();
+                // Joining an empty path would add a trailing slash
+                let manifest_path = if rel_to_source_dir.as_os_str().is_empty() {
+                    storage_rel_path.to_string_lossy().to_string()
+                } else {
+                    storage_rel_path
+                        .join(rel_to_source_dir)
+                        .to_string_lossy()
+                        .to_string()
+                };
 
-                // Check if this file should be ignored
-                if should_ignore_path(entry_path, root, &ignore_patterns) {
-                    continue; // Skip ignored files silently during directory traversal
-                }
-
-                if entry_path.is_file() {
-                    let rel_to_source_dir: &Path =
-                        entry_path.strip_prefix(&source_path_canon).unwrap();
-                    let manifest_path: PathBuf = storage_rel_path.join(rel_to_source_dir);
-
-                    match fs::read(entry_path) {
-                        Ok(content) => match store_content_in_objects(root, &content) {
-                            Ok(hash) => {
-                                manifest
-                                    .files
-                                    .insert(manifest_path.to_string_lossy().to_string(), hash);
-                            }
-                            Err(e) => {
-                                eprintln!(
-                                    "{}",
-                                    format!(
-                                        "Warning: Failed to store {} - Error: {} - skipped",
-                                        entry_path.display(),
-                                        e
-                                    )
-                                    .yellow()
-                                );
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!(
-                                "{}",
-                                format!(
-                                    "Warning: Failed to read {} - Error: {} - skipped",
-                                    entry_path.display(),
-                                    e
-                                )
-                                .yellow()
-                            );
-                        }
+                if entry.file_type().is_dir() {
+                    // Kept only if nothing ends up below it, see prune_implied_dirs
+                    if !manifest_path.is_empty() {
+                        manifest.dirs.insert(manifest_path);
                     }
+                } else if entry.file_type().is_file() || entry.file_type().is_symlink() {
+                    // Links are recorded as links, never followed
+                    pending.push(PendingFile {
+                        path: entry_path.to_path_buf(),
+                        manifest_path,
+                        metadata: fs::symlink_metadata(entry_path).ok(),
+                    });
                 }
             }
-        } else if source_path_canon.is_file() {
-            match fs::read(&source_path_canon) {
-                Ok(content) => match store_content_in_objects(root, &content) {
-                    Ok(hash) => {
-                        manifest
-                            .files
-                            .insert(storage_rel_path.to_string_lossy().to_string(), hash);
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "{}",
-                            format!(
-                                "Warning: Failed to store {} - Error: {} - skipped",
-                                source_path_canon.display(),
-                                e
-                            )
-                            .yellow()
-                        );
-                    }
-                },
-                Err(e) => {
-                    eprintln!(
-                        "{}",
-                        format!(
-                            "Warning: Failed to read {} - Error: {} - skipped",
-                            source_path_canon.display(),
-                            e
-                        )
-                        .yellow()
-                    );
+        } else if source_metadata.is_file() || source_metadata.file_type().is_symlink() {
+            pending.push(PendingFile {
+                manifest_path: storage_rel_path.to_string_lossy().to_string(),
+                metadata: Some(source_metadata),
+                path: source_path_canon,
+            });
+        }
+    }
+
+    // Files whose stat cache entry is fresh and whose object is already
+    // stored need no work; everything else is read, hashed and stored on the
+    // worker pool
+    let mut to_store: Vec<PendingFile> = Vec::new();
+    for file in pending {
+        if let Some(metadata) = &file.metadata
+            && let Some(hash) = cache.lookup(&file.manifest_path, metadata)
+            && object_exists(root, &hash)
+        {
+            let entry = FileEntry::from_metadata(hash, file.metadata.as_ref());
+            manifest.files.insert(file.manifest_path, entry);
+        } else {
+            to_store.push(file);
+        }
+    }
+
+    let jobs = match options.jobs {
+        0 => resolve_jobs(Config::load()?.snapshot_jobs()),
+        jobs => jobs,
+    };
+    let stored = parallel_map(&to_store, jobs, |file| {
+        store_file(root, &file.path, file.metadata.as_ref())
+    });
+
+    // Warnings and cache updates happen here, in walk order, so the output
+    // does not depend on which worker finished first
+    for (file, result) in to_store.into_iter().zip(stored) {
+        match result {
+            Ok(hash) => {
+                if let Some(metadata) = &file.metadata {
+                    cache.update(&file.manifest_path, metadata, &hash);
                 }
+                let entry = FileEntry::from_metadata(hash, file.metadata.as_ref());
+                manifest.files.insert(file.manifest_path, entry);
             }
+            Err(warning) => eprintln!("{}", warning.yellow()),
         }
     }
 
+    manifest.prune_implied_dirs();
+
+    if covers_root {
+        cache.prune_unseen();
+    }
+    cache.save(root)?;
+
     Ok(manifest)
 }
+
+/// Read, hash and store one file (or a symlink's target). Returns the
+/// warning to print if the file has to be skipped.
+fn store_file(
+    root: &Path,
+    path: &Path,
+    metadata: Option<&fs::Metadata>,
+) -> std::result::Result<String, String> {
+    let content = read_entry_content(path, metadata).map_err(|e| {
+        format!(
+            "Warning: Failed to read {} - Error: {} - skipped",
+            path.display(),
+            e
+        )
+    })?;
+
+    store_content_in_objects(root, &content).map_err(|e| {
+        format!(
+            "Warning: Failed to store {} - Error: {} - skipped",
+            path.display(),
+            e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_build_matches_serial_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        for i in 0..40 {
+            let path = root.join(format!("dir{}/file{}.txt", i % 5, i));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("content {}", i % 7)).unwrap();
+        }
+        let build = |jobs| {
+            let options = BuildOptions { rehash: true, jobs };
+            build_snapshot_manifest(root, &[root.to_path_buf()], options).unwrap()
+        };
+
+        let serial = build(1);
+        let parallel = build(8);
+        assert_eq!(serial.files.len(), 40);
+        assert_eq!(serial.files, parallel.files);
+        assert!(
+            serial
+                .files
+                .values()
+                .all(|entry| object_exists(root, &entry.hash))
+        );
+    }
+
+    fn manifest(files: &[(&str, &str)]) -> SnapshotManifest {
+        SnapshotManifest {
+            files: files
+                .iter()
+                .map(|(path, hash)| (path.to_string(), FileEntry::regular(hash.to_string())))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn overlay_replaces_only_the_covered_paths() {
+        let parent = manifest(&[
+            ("src/a.rs", "a1"),
+            ("src/gone.rs", "g1"),
+            ("srcs.txt", "s1"),
+            ("README.md", "r1"),
+        ]);
+        let changes = manifest(&[("src/a.rs", "a2"), ("src/new.rs", "n1")]);
+
+        let merged = parent.overlay(changes, &["src".to_string()]);
+        // Deleted files under the prefix drop out, siblings sharing the
+        // prefix as a string are untouched
+        assert_eq!(
+            merged.files,
+            manifest(&[
+                ("src/a.rs", "a2"),
+                ("src/new.rs", "n1"),
+                ("srcs.txt", "s1"),
+                ("README.md", "r1"),
+            ])
+            .files
+        );
+    }
+
+    #[test]
+    fn overlay_of_a_single_file_keeps_everything_else() {
+        let parent = manifest(&[("a.txt", "a1"), ("b.txt", "b1")]);
+        let merged = parent.overlay(manifest(&[("a.txt", "a2")]), &["a.txt".to_string()]);
+        assert_eq!(
+            merged.files,
+            manifest(&[("a.txt", "a2"), ("b.txt", "b1")]).files
+        );
+
+        // A file that was removed from disk disappears when named explicitly
+        let merged = parent.overlay(manifest(&[]), &["b.txt".to_string()]);
+        assert_eq!(merged.files, manifest(&[("a.txt", "a1")]).files);
+
+        // The empty prefix covers the whole tree
+        let merged = parent.overlay(manifest(&[("c.txt", "c1")]), &[String::new()]);
+        assert_eq!(merged.files, manifest(&[("c.txt", "c1")]).files);
+    }
+
+    #[test]
+    fn only_leaf_directories_are_kept() {
+        let mut dirs: BTreeSet<String> = ["a", "a/b", "a/b/c", "d", "e"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let files = ["d/file.txt".to_string()];
+        retain_leaf_dirs(&mut dirs, files.iter());
+        assert_eq!(dirs.into_iter().collect::<Vec<_>>(), vec!["a/b/c", "e"]);
+    }
+
+    #[test]
+    fn has_dir_sees_recorded_and_implied_directories() {
+        let mut manifest = manifest(&[("src/lib.rs", "h")]);
+        manifest.dirs.insert("logs/old".to_string());
+        for dir in ["src", "logs", "logs/old"] {
+            assert!(manifest.has_dir(dir), "{}", dir);
+        }
+        for dir in ["sr", "src/lib.rs", "logs/ol", "other"] {
+            assert!(!manifest.has_dir(dir), "{}", dir);
+        }
+    }
+
+    #[test]
+    fn build_records_empty_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        fs::create_dir_all(root.join("empty/nested")).unwrap();
+        fs::create_dir_all(root.join("full")).unwrap();
+        fs::write(root.join("full/file.txt"), "x").unwrap();
+        let options = BuildOptions {
+            rehash: true,
+            jobs: 1,
+        };
+
+        let manifest = build_snapshot_manifest(&root, &[PathBuf::from(".")], options).unwrap();
+        assert_eq!(
+            manifest.dirs.iter().collect::<Vec<_>>(),
+            vec!["empty/nested"]
+        );
+        assert!(manifest.files.contains_key("full/file.txt"));
+
+        // A partial snap of an empty directory records just that directory
+        let manifest = build_snapshot_manifest(&root, &[root.join("empty")], options).unwrap();
+        assert_eq!(
+            manifest.dirs.iter().collect::<Vec<_>>(),
+            vec!["empty/nested"]
+        );
+    }
+
+    #[test]
+    fn overlay_replaces_covered_directories() {
+        let mut parent = manifest(&[("keep.txt", "k")]);
+        parent
+            .dirs
+            .extend(["logs".to_string(), "tmp/cache".to_string()]);
+        let mut changes = manifest(&[("logs/app.log", "l")]);
+        changes.dirs.insert("tmp/new".to_string());
+
+        let merged = parent.overlay(changes, &["logs".to_string(), "tmp".to_string()]);
+        assert_eq!(merged.dirs.iter().collect::<Vec<_>>(), vec!["tmp/new"]);
+        assert!(merged.files.contains_key("logs/app.log"));
+        assert!(merged.files.contains_key("keep.txt"));
+    }
+
+    #[test]
+    fn build_does_not_descend_into_ignored_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(REPO_DIRNAME)).unwrap();
+        fs::write(root.join(".vibeignore"), "build/\n!build/keep.txt\n").unwrap();
+        fs::create_dir_all(root.join("build/deep")).unwrap();
+        fs::write(root.join("build/keep.txt"), "k").unwrap();
+        fs::write(root.join("build/deep/out.o"), "o").unwrap();
+        fs::write(root.join("main.rs"), "m").unwrap();
+
+        let manifest =
+            build_snapshot_manifest(root, &[root.to_path_buf()], BuildOptions::default()).unwrap();
+        let files: Vec<&str> = manifest.files.keys().map(String::as_str).collect();
+        // As in git, a file cannot be re-included once its directory is ignored
+        assert_eq!(files, vec![".vibeignore", "main.rs"]);
+        assert!(manifest.dirs.iter().all(|dir| !dir.starts_with("build")));
+    }
+}
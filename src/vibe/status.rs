@@ -0,0 +1,223 @@
+use crate::config::Config;
+use crate::error::Result;
+use colored::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use walkdir;
+
+use super::constants::REPO_DIRNAME;
+use super::entry::{FileEntry, read_entry_content};
+use super::ignore::{read_ignore_rules, should_ignore_path};
+use super::index::StatCache;
+use super::lock::RepoLock;
+use super::objects::hash_file_content;
+use super::repo::read_head;
+use super::snapshot::{SnapshotManifest, load_snapshot_manifest, retain_leaf_dirs};
+use super::utils::{parallel_map, resolve_jobs};
+
+/// Differences between the working tree and a checkpoint manifest
+#[derive(Debug, Default)]
+pub struct WorkspaceStatus {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    pub ignored: Vec<String>,
+}
+
+impl WorkspaceStatus {
+    pub fn is_clean(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// Walk the working tree with the same ignore rules used for snapshots and
+/// return the relative paths of all files. Ignored entries are collected into
+/// `ignored` when provided.
+pub fn list_workspace_files(root: &Path, ignored: Option<&mut Vec<String>>) -> Result<Vec<String>> {
+    Ok(walk_workspace(root, ignored)?.0)
+}
+
+/// Directories in the working tree with no files below them
+pub fn list_empty_workspace_dirs(root: &Path) -> Result<BTreeSet<String>> {
+    let (files, mut dirs) = walk_workspace(root, None)?;
+    retain_leaf_dirs(&mut dirs, files.iter());
+    Ok(dirs)
+}
+
+/// Returns the relative paths of all files and of all directories
+fn walk_workspace(
+    root: &Path,
+    mut ignored: Option<&mut Vec<String>>,
+) -> Result<(Vec<String>, BTreeSet<String>)> {
+    let ignore_rules = read_ignore_rules(root)?;
+    let mut files: Vec<String> = Vec::new();
+    let mut dirs: BTreeSet<String> = BTreeSet::new();
+
+    let walker = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            if !should_ignore_path(entry.path(), root, &ignore_rules) {
+                return true;
+            }
+            // The .vibe store and .git are always skipped and never worth listing
+            let internal = entry.depth() == 1
+                && (entry.file_name() == REPO_DIRNAME || entry.file_name() == ".git");
+            if let Some(list) = ignored.as_deref_mut()
+                && !internal
+            {
+                let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                let mut rel_str = rel.to_string_lossy().to_string();
+                if entry.file_type().is_dir() {
+                    rel_str.push('/');
+                }
+                list.push(rel_str);
+            }
+            false
+        });
+
+    for entry_result in walker {
+        let entry: walkdir::DirEntry = match entry_result {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!("Warning: Error walking working tree: {} - skipped entry", e).yellow()
+                );
+                continue;
+            }
+        };
+
+        let Ok(rel) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        // Symlinks are listed as entries of their own, never followed
+        if entry.file_type().is_dir() {
+            dirs.insert(rel.to_string_lossy().to_string());
+        } else if entry.file_type().is_file() || entry.file_type().is_symlink() {
+            files.push(rel.to_string_lossy().to_string());
+        }
+    }
+
+    Ok((files, dirs))
+}
+
+/// Describe the working tree the way a snapshot of it would, without writing
+/// anything to the object store
+pub fn scan_workspace(root: &Path, ignored: Option<&mut Vec<String>>) -> Result<SnapshotManifest> {
+    let mut files: BTreeMap<String, FileEntry> = BTreeMap::new();
+    let mut cache = StatCache::load(root, false);
+    let mut to_hash: Vec<(String, Option<fs::Metadata>)> = Vec::new();
+
+    let (paths, dirs) = walk_workspace(root, ignored)?;
+    for rel_path in paths {
+        let metadata = fs::symlink_metadata(root.join(&rel_path)).ok();
+        if let Some(metadata) = &metadata
+            && let Some(hash) = cache.lookup(&rel_path, metadata)
+        {
+            let entry = FileEntry::from_metadata(hash, Some(metadata));
+            files.insert(rel_path, entry);
+        } else {
+            to_hash.push((rel_path, metadata));
+        }
+    }
+
+    let jobs = resolve_jobs(Config::load()?.snapshot_jobs());
+    let hashed = parallel_map(&to_hash, jobs, |(rel_path, metadata)| {
+        read_entry_content(&root.join(rel_path), metadata.as_ref())
+            .map(|content| hash_file_content(&content))
+    });
+
+    for ((rel_path, metadata), result) in to_hash.into_iter().zip(hashed) {
+        match result {
+            Ok(hash) => {
+                if let Some(metadata) = &metadata {
+                    cache.update(&rel_path, metadata, &hash);
+                }
+                let entry = FileEntry::from_metadata(hash, metadata.as_ref());
+                files.insert(rel_path, entry);
+            }
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Warning: Failed to read {} - Error: {} - skipped",
+                        root.join(&rel_path).display(),
+                        e
+                    )
+                    .yellow()
+                );
+            }
+        }
+    }
+
+    cache.prune_unseen();
+    // Status and diff run without the repository lock, so the index is only
+    // written when nothing else (e.g. a snap saving its own) holds it; if
+    // something does, the hashing is simply redone next time
+    if let Ok(Some(_lock)) = RepoLock::try_acquire(root, "status") {
+        cache.save(root)?;
+    }
+
+    let mut workspace = SnapshotManifest { files, dirs };
+    workspace.prune_implied_dirs();
+    Ok(workspace)
+}
+
+/// Compare the working tree against a manifest (or an empty tree when `None`)
+pub fn compute_status(
+    root: &Path,
+    manifest: Option<&SnapshotManifest>,
+    include_ignored: bool,
+) -> Result<WorkspaceStatus> {
+    let mut status = WorkspaceStatus::default();
+    let workspace = if include_ignored {
+        scan_workspace(root, Some(&mut status.ignored))?
+    } else {
+        scan_workspace(root, None)?
+    };
+
+    let empty = SnapshotManifest::default();
+    let snapshot = manifest.unwrap_or(&empty);
+
+    let all_paths: BTreeSet<&String> = workspace
+        .files
+        .keys()
+        .chain(snapshot.files.keys())
+        .collect();
+    for path in all_paths {
+        match (snapshot.files.get(path), workspace.files.get(path)) {
+            (None, Some(_)) => status.added.push(path.clone()),
+            (Some(_), None) => status.deleted.push(path.clone()),
+            (Some(old), Some(new)) if new.differs_from(old) => status.modified.push(path.clone()),
+            _ => {}
+        }
+    }
+
+    // Directories are listed with a trailing slash, like ignored ones
+    for dir in &snapshot.dirs {
+        if !workspace.has_dir(dir) {
+            status.deleted.push(format!("{}/", dir));
+        }
+    }
+    for dir in &workspace.dirs {
+        if !snapshot.has_dir(dir) {
+            status.added.push(format!("{}/", dir));
+        }
+    }
+    status.added.sort();
+    status.deleted.sort();
+
+    Ok(status)
+}
+
+/// Compare the working tree against the checkpoint HEAD points at
+pub fn status_against_head(root: &Path, include_ignored: bool) -> Result<WorkspaceStatus> {
+    let manifest = match read_head(root)?.1 {
+        Some(id) => Some(load_snapshot_manifest(root, &id)?),
+        None => None,
+    };
+    compute_status(root, manifest.as_ref(), include_ignored)
+}
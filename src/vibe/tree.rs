@@ -0,0 +1,373 @@
+use crate::error::{AppError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+use super::entry::FileEntry;
+use super::objects::{read_content_from_objects, store_content_in_objects};
+use super::snapshot::SnapshotManifest;
+
+/// One directory of a snapshot, stored as an object of its own. A tree's hash
+/// covers everything below it, so checkpoints share unchanged subtrees and
+/// comparisons can skip them without reading them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Tree {
+    entries: BTreeMap<String, TreeNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum TreeNode {
+    Tree { tree: String },
+    File(FileEntry),
+}
+
+/// A directory being assembled from manifest paths before it is stored
+#[derive(Default)]
+struct DirBuilder {
+    files: BTreeMap<String, FileEntry>,
+    dirs: BTreeMap<String, DirBuilder>,
+}
+
+impl DirBuilder {
+    fn dir_at(&mut self, path: &str) -> &mut DirBuilder {
+        path.split('/').fold(self, |dir, name| {
+            dir.dirs.entry(name.to_string()).or_default()
+        })
+    }
+
+    fn store(self, root: &Path) -> Result<String> {
+        let mut tree = Tree::default();
+        for (name, entry) in self.files {
+            tree.entries.insert(name, TreeNode::File(entry));
+        }
+        for (name, dir) in self.dirs {
+            let hash = dir.store(root)?;
+            tree.entries.insert(name, TreeNode::Tree { tree: hash });
+        }
+        let content = serde_json::to_vec(&tree)
+            .map_err(|e| AppError::ManifestSerializationError(e.to_string()))?;
+        store_content_in_objects(root, &content)
+    }
+}
+
+/// Store `manifest` as tree objects and return the hash of the root tree
+pub fn write_tree(root: &Path, manifest: &SnapshotManifest) -> Result<String> {
+    let mut top = DirBuilder::default();
+    for (path, entry) in &manifest.files {
+        let (dir, name) = match path.rsplit_once('/') {
+            Some((dir, name)) => (top.dir_at(dir), name),
+            None => (&mut top, path.as_str()),
+        };
+        dir.files.insert(name.to_string(), entry.clone());
+    }
+    for dir in &manifest.dirs {
+        top.dir_at(dir);
+    }
+    top.store(root)
+}
+
+fn read_tree(root: &Path, hash: &str) -> Result<Tree> {
+    let content = read_content_from_objects(root, hash)?;
+    serde_json::from_slice(&content)
+        .map_err(|e| AppError::ManifestDeserializationError(format!("tree {}: {}", hash, e)))
+}
+
+fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Rebuild the full manifest stored under the root tree `hash`
+pub fn load_tree(root: &Path, hash: &str) -> Result<SnapshotManifest> {
+    let mut manifest = SnapshotManifest::default();
+    flatten_into(root, hash, "", &mut manifest, None)?;
+    Ok(manifest)
+}
+
+/// Like `load_tree`, but a tree object that cannot be read is skipped along
+/// with everything below it instead of failing the whole load. Returns what
+/// could be read, and the directory path and hash of each skipped tree
+/// (`""` for the root).
+pub fn load_tree_partial(root: &Path, hash: &str) -> (SnapshotManifest, Vec<(String, String)>) {
+    let mut manifest = SnapshotManifest::default();
+    let mut unreadable = Vec::new();
+    // Cannot fail: every read error is collected instead
+    let _ = flatten_into(root, hash, "", &mut manifest, Some(&mut unreadable));
+    (manifest, unreadable)
+}
+
+fn flatten_into(
+    root: &Path,
+    hash: &str,
+    prefix: &str,
+    manifest: &mut SnapshotManifest,
+    mut unreadable: Option<&mut Vec<(String, String)>>,
+) -> Result<()> {
+    let tree = match (read_tree(root, hash), unreadable.as_deref_mut()) {
+        (Ok(tree), _) => tree,
+        (Err(_), Some(unreadable)) => {
+            unreadable.push((prefix.to_string(), hash.to_string()));
+            return Ok(());
+        }
+        (Err(e), None) => return Err(e),
+    };
+    if tree.entries.is_empty() && !prefix.is_empty() {
+        manifest.dirs.insert(prefix.to_string());
+    }
+    for (name, node) in tree.entries {
+        let path = join_path(prefix, &name);
+        match node {
+            TreeNode::File(entry) => {
+                manifest.files.insert(path, entry);
+            }
+            TreeNode::Tree { tree } => {
+                flatten_into(root, &tree, &path, manifest, unreadable.as_deref_mut())?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The parts of two trees that differ, as a pair of partial manifests. Any
+/// subtree with the same hash on both sides is left out of both without
+/// being read, so comparing the results gives the same changes as comparing
+/// the full manifests.
+pub fn diff_trees(
+    root: &Path,
+    old: &str,
+    new: &str,
+) -> Result<(SnapshotManifest, SnapshotManifest)> {
+    let mut sides = (SnapshotManifest::default(), SnapshotManifest::default());
+    diff_into(root, Some(old), Some(new), "", &mut sides)?;
+    Ok(sides)
+}
+
+fn diff_into(
+    root: &Path,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    sides: &mut (SnapshotManifest, SnapshotManifest),
+) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let read_side = |hash: Option<&str>, manifest: &mut SnapshotManifest| -> Result<Tree> {
+        let Some(hash) = hash else {
+            return Ok(Tree::default());
+        };
+        let tree = read_tree(root, hash)?;
+        if tree.entries.is_empty() && !prefix.is_empty() {
+            manifest.dirs.insert(prefix.to_string());
+        }
+        Ok(tree)
+    };
+    let old_tree = read_side(old, &mut sides.0)?;
+    let new_tree = read_side(new, &mut sides.1)?;
+
+    let names: BTreeSet<&String> = old_tree
+        .entries
+        .keys()
+        .chain(new_tree.entries.keys())
+        .collect();
+    for name in names {
+        let (old_node, new_node) = (old_tree.entries.get(name), new_tree.entries.get(name));
+        if old_node == new_node {
+            continue;
+        }
+        let path = join_path(prefix, name);
+        let mut subtrees = (None, None);
+        if let Some(node) = old_node {
+            subtrees.0 = split_node(node, &path, &mut sides.0);
+        }
+        if let Some(node) = new_node {
+            subtrees.1 = split_node(node, &path, &mut sides.1);
+        }
+        if subtrees.0.is_some() || subtrees.1.is_some() {
+            diff_into(root, subtrees.0, subtrees.1, &path, sides)?;
+        }
+    }
+    Ok(())
+}
+
+/// Record a file node in `manifest`, or hand back the hash of a subtree
+fn split_node<'a>(
+    node: &'a TreeNode,
+    path: &str,
+    manifest: &mut SnapshotManifest,
+) -> Option<&'a str> {
+    match node {
+        TreeNode::File(entry) => {
+            manifest.files.insert(path.to_string(), entry.clone());
+            None
+        }
+        TreeNode::Tree { tree } => Some(tree),
+    }
+}
+
+/// Add every tree object under `hash` and every file object they name to
+/// `objects`. Trees already in `visited` are skipped along with everything
+/// below them, so walking many checkpoints that share subtrees stays cheap.
+pub fn collect_tree_objects(
+    root: &Path,
+    hash: &str,
+    visited: &mut HashSet<String>,
+    objects: &mut HashSet<String>,
+) -> Result<()> {
+    if !visited.insert(hash.to_string()) {
+        return Ok(());
+    }
+    objects.insert(hash.to_string());
+    for node in read_tree(root, hash)?.entries.into_values() {
+        match node {
+            TreeNode::File(entry) => {
+                objects.insert(entry.hash);
+            }
+            TreeNode::Tree { tree } => collect_tree_objects(root, &tree, visited, objects)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vibe::objects::object_path;
+    use std::fs;
+
+    fn manifest(files: &[(&str, &str)], dirs: &[&str]) -> SnapshotManifest {
+        SnapshotManifest {
+            files: files
+                .iter()
+                .map(|(path, hash)| (path.to_string(), FileEntry::regular(hash.to_string())))
+                .collect(),
+            dirs: dirs.iter().map(|dir| dir.to_string()).collect(),
+        }
+    }
+
+    fn subtree_hash(root: &Path, tree: &str, name: &str) -> String {
+        match &read_tree(root, tree).unwrap().entries[name] {
+            TreeNode::Tree { tree } => tree.clone(),
+            TreeNode::File(_) => panic!("{} is a file", name),
+        }
+    }
+
+    #[test]
+    fn write_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = manifest(
+            &[("top", "1"), ("a/b/c", "2"), ("a/d", "3")],
+            &["empty", "a/b/also-empty"],
+        );
+        let hash = write_tree(dir.path(), &original).unwrap();
+        assert_eq!(load_tree(dir.path(), &hash).unwrap(), original);
+        // Same content, same hash
+        assert_eq!(write_tree(dir.path(), &original).unwrap(), hash);
+    }
+
+    #[test]
+    fn unchanged_subtrees_share_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_tree(dir.path(), &manifest(&[("a/x", "1"), ("b/y", "2")], &[])).unwrap();
+        let new = write_tree(dir.path(), &manifest(&[("a/x", "1"), ("b/y", "3")], &[])).unwrap();
+        assert_ne!(old, new);
+        assert_eq!(
+            subtree_hash(dir.path(), &old, "a"),
+            subtree_hash(dir.path(), &new, "a")
+        );
+    }
+
+    #[test]
+    fn diff_trees_returns_only_differing_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_tree(
+            dir.path(),
+            &manifest(
+                &[
+                    ("same/1", "1"),
+                    ("same/2", "2"),
+                    ("changed/x", "3"),
+                    ("d", "4"),
+                ],
+                &["gone"],
+            ),
+        )
+        .unwrap();
+        let new = write_tree(
+            dir.path(),
+            &manifest(
+                &[
+                    ("same/1", "1"),
+                    ("same/2", "2"),
+                    ("changed/x", "5"),
+                    ("changed/y", "6"),
+                    ("d/z", "4"),
+                ],
+                &["added"],
+            ),
+        )
+        .unwrap();
+
+        // The shared subtree is never read
+        let shared = subtree_hash(dir.path(), &old, "same");
+        fs::remove_file(object_path(dir.path(), &shared)).unwrap();
+
+        let (old_side, new_side) = diff_trees(dir.path(), &old, &new).unwrap();
+        assert_eq!(
+            old_side,
+            manifest(&[("changed/x", "3"), ("d", "4")], &["gone"])
+        );
+        assert_eq!(
+            new_side,
+            manifest(
+                &[("changed/x", "5"), ("changed/y", "6"), ("d/z", "4")],
+                &["added"]
+            )
+        );
+
+        let (old_side, new_side) = diff_trees(dir.path(), &new, &new).unwrap();
+        assert_eq!(old_side, SnapshotManifest::default());
+        assert_eq!(new_side, SnapshotManifest::default());
+    }
+
+    #[test]
+    fn collect_tree_objects_visits_shared_trees_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = write_tree(dir.path(), &manifest(&[("a/x", "1"), ("y", "2")], &[])).unwrap();
+        let second = write_tree(dir.path(), &manifest(&[("a/x", "1"), ("y", "3")], &[])).unwrap();
+        let mut visited = HashSet::new();
+        let mut objects = HashSet::new();
+        collect_tree_objects(dir.path(), &first, &mut visited, &mut objects).unwrap();
+        let subtree = subtree_hash(dir.path(), &first, "a");
+        let expected: HashSet<String> =
+            [first.clone(), subtree.clone(), "1".into(), "2".into()].into();
+        assert_eq!(objects, expected);
+
+        // The shared subtree is skipped, so it may as well be missing
+        fs::remove_file(object_path(dir.path(), &subtree)).unwrap();
+        collect_tree_objects(dir.path(), &second, &mut visited, &mut objects).unwrap();
+        assert!(objects.contains(&second) && objects.contains("3"));
+    }
+
+    #[test]
+    fn partial_load_skips_unreadable_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = write_tree(dir.path(), &manifest(&[("a/x", "1"), ("b/y", "2")], &[])).unwrap();
+        let broken = subtree_hash(dir.path(), &hash, "a");
+        fs::write(object_path(dir.path(), &broken), "garbage").unwrap();
+
+        assert!(load_tree(dir.path(), &hash).is_err());
+        let (loaded, unreadable) = load_tree_partial(dir.path(), &hash);
+        assert_eq!(loaded, manifest(&[("b/y", "2")], &[]));
+        assert_eq!(unreadable, vec![("a".to_string(), broken)]);
+
+        let missing = "0".repeat(64);
+        let (loaded, unreadable) = load_tree_partial(dir.path(), &missing);
+        assert_eq!(loaded, SnapshotManifest::default());
+        assert_eq!(unreadable, vec![(String::new(), missing)]);
+    }
+}
@@ -1,12 +1,236 @@
+use crate::error::{AppError, Result};
 use chrono;
 use hex;
 use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-// Helper function to generate a unique ID
-pub fn generate_id(track: &str) -> String {
+/// Generate a checkpoint ID from what the checkpoint records (its track,
+/// parent and a digest of its manifest) plus the time and process, so two
+/// snaps only collide if all of those do. 16 hex digits.
+pub fn generate_id(track: &str, parent_id: Option<&str>, manifest_digest: &str) -> String {
     let mut hasher = Sha256::new();
     let timestamp: i64 = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
-    hasher.update(format!("{}{}", track, timestamp).as_bytes());
+    hasher.update(
+        format!(
+            "{}\0{}\0{}\0{}\0{}",
+            track,
+            parent_id.unwrap_or_default(),
+            manifest_digest,
+            timestamp,
+            std::process::id()
+        )
+        .as_bytes(),
+    );
     let result = hasher.finalize();
-    hex::encode(&result[..4]).to_uppercase() // Use first 4 bytes for an 8-char hex string
+    hex::encode(&result[..8]).to_uppercase()
+}
+
+/// Resolve a requested worker count: 0 means one per available CPU
+pub fn resolve_jobs(requested: usize) -> usize {
+    if requested > 0 {
+        return requested;
+    }
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Apply `f` to every item on a pool of at most `jobs` scoped threads. The
+/// results come back in the same order as `items`, whatever order the work
+/// finished in.
+pub fn parallel_map<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let jobs = jobs.clamp(1, items.len().max(1));
+    if jobs == 1 {
+        return items.iter().map(&f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = std::iter::repeat_with(|| None).take(items.len()).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break;
+                        };
+                        done.push((index, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        for worker in workers {
+            // A panicking worker takes the whole operation down with it
+            let done = worker
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            for (index, result) in done {
+                results[index] = Some(result);
+            }
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.expect("every item is processed by a worker"))
+        .collect()
+}
+
+/// Parse a duration like "30m", "2h", "1h30m" or "45s" into seconds
+pub fn parse_duration(s: &str) -> Result<i64> {
+    let s = s.trim().to_lowercase();
+
+    // Try to parse formats like "30m", "2h", "1h30m", "45s"
+    let mut total_seconds = 0i64;
+    let mut current_num = String::new();
+
+    for ch in s.chars() {
+        if ch.is_ascii_digit() {
+            current_num.push(ch);
+        } else {
+            if current_num.is_empty() {
+                return Err(AppError::Generic(format!("Invalid duration format: {}", s)));
+            }
+
+            let num: i64 = current_num.parse().map_err(|_| {
+                AppError::Generic(format!("Invalid number in duration: {}", current_num))
+            })?;
+
+            let multiplier = match ch {
+                's' => 1,
+                'm' => 60,
+                'h' => 3600,
+                'd' => 86400,
+                _ => return Err(AppError::Generic(format!("Invalid time unit: {}", ch))),
+            };
+
+            total_seconds += num * multiplier;
+            current_num.clear();
+        }
+    }
+
+    if total_seconds == 0 {
+        return Err(AppError::Generic(format!("Invalid duration: {}", s)));
+    }
+
+    Ok(total_seconds)
+}
+
+/// Infix of the temporary files `write_atomic` writes next to their target
+pub const TEMP_FILE_INFIX: &str = ".tmp-";
+
+/// Write `contents` to a temporary file beside `path` and rename it into
+/// place, so readers see either the old file or the complete new one, never
+/// a partial write
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(
+        "{}{}{}-{}",
+        file_name,
+        TEMP_FILE_INFIX,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = result.and_then(|_| fs::rename(&tmp_path, path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Whether a process with this PID is alive
+#[cfg(unix)]
+pub fn is_process_running(pid: i32) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_process_running(_pid: i32) -> bool {
+    // On non-Unix systems, assume it's running
+    true
+}
+
+/// Canonicalize `path` without resolving its last component, so a symlink
+/// named on the command line is snapped as the link itself
+pub fn canonicalize_keeping_link(path: &Path) -> std::io::Result<PathBuf> {
+    let is_link = fs::symlink_metadata(path)?.file_type().is_symlink();
+    match (is_link, path.parent(), path.file_name()) {
+        (true, Some(parent), Some(name)) => Ok(parent.canonicalize()?.join(name)),
+        _ => path.canonicalize(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parallel_map_keeps_input_order() {
+        let items: Vec<u64> = (0..200).collect();
+        let results = parallel_map(&items, 8, |&n| {
+            // Later items finish first
+            std::thread::sleep(Duration::from_micros(200 - n));
+            n * 2
+        });
+        assert_eq!(results, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parallel_map_runs_at_most_jobs_at_once() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..64).collect();
+        parallel_map(&items, 3, |_| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(1));
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn parallel_map_edge_cases() {
+        let empty: Vec<u32> = Vec::new();
+        assert!(parallel_map(&empty, 4, |n| *n).is_empty());
+        // Zero jobs still does the work on the calling thread
+        assert_eq!(parallel_map(&[1, 2, 3], 0, |n| n + 1), vec![2, 3, 4]);
+        assert!(resolve_jobs(0) >= 1);
+        assert_eq!(resolve_jobs(5), 5);
+    }
+
+    #[test]
+    fn generated_ids_are_sixteen_hex_digits_and_distinct() {
+        let first = generate_id("main", None, "digest");
+        let second = generate_id("main", None, "digest");
+        assert_eq!(first.len(), 16);
+        assert!(
+            first
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b))
+        );
+        assert_ne!(first, second);
+        assert_ne!(first, generate_id("main", Some(&first), "digest"));
+    }
 }